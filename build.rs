@@ -0,0 +1,38 @@
+//! Refuses to build a release while a GitHub token is around to be compiled in.
+//!
+//! Tokens are read at runtime. A release built with `REPO_PRIVATE_KEY` in its
+//! environment, or in the `.env` file the build reads constants from, most likely comes
+//! from a pipeline that still expects it to be compiled in.
+
+const TOKEN_ENV: &str = "REPO_PRIVATE_KEY";
+
+fn main() {
+    let env_file = std::path::Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join(".env");
+    println!("cargo:rerun-if-env-changed={TOKEN_ENV}");
+    println!("cargo:rerun-if-changed={}", env_file.display());
+
+    if std::env::var("PROFILE").as_deref() != Ok("release") {
+        return;
+    }
+    if std::env::var_os(TOKEN_ENV).is_some() {
+        panic!("{TOKEN_ENV} is set while building a release. Tokens are read at runtime and must not be part of the build environment.");
+    }
+    let contents = std::fs::read_to_string(&env_file).unwrap_or_default();
+    if sets_token(&contents) {
+        panic!(
+            "{} sets {TOKEN_ENV}. Tokens are read at runtime; remove it before building a release.",
+            env_file.display()
+        );
+    }
+}
+
+/// Whether a `.env` file assigns the token, e.g. `REPO_PRIVATE_KEY=...` or
+/// `export REPO_PRIVATE_KEY="..."`.
+fn sets_token(contents: &str) -> bool {
+    contents.lines().any(|line| {
+        let line = line.trim_start();
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        line.split_once('=')
+            .is_some_and(|(key, _)| key.trim() == TOKEN_ENV)
+    })
+}
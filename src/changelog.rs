@@ -0,0 +1,147 @@
+//! Release notes shown before an update, rendered from the Markdown of each release.
+
+use semver::Version;
+
+use crate::release_source::Release;
+use crate::updater::Channel;
+
+/// Notes of every release after `installed` up to and including `target`, newest first.
+pub fn render(releases: &[Release], installed: &str, target: &Release, channel: Channel) -> String {
+    let (Ok(installed), Some(target)) = (Version::parse(installed), target.version()) else {
+        return String::new();
+    };
+    let mut covered: Vec<(Version, &Release)> = releases
+        .iter()
+        .filter(|r| r.is_on_channel(channel))
+        .filter_map(|r| Some((r.version()?, r)))
+        .filter(|(version, _)| channel.accepts(version))
+        .filter(|(version, _)| *version > installed && *version <= target)
+        .collect();
+    covered.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let mut out = String::new();
+    for (_, release) in covered {
+        out.push_str(&heading(&release.tag_name, '='));
+        match release.body.as_deref().map(str::trim) {
+            Some(body) if !body.is_empty() => out.push_str(&render_markdown(body)),
+            _ => out.push_str("No release notes.\n"),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Turns GitHub-flavoured Markdown into plain text that reads well in a terminal.
+pub fn render_markdown(markdown: &str) -> String {
+    let mut out = String::new();
+    let mut last_blank = true;
+    for line in markdown.lines() {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            if !last_blank {
+                out.push('\n');
+            }
+            last_blank = true;
+            continue;
+        }
+        last_blank = false;
+
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        let level = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+            out.push_str(&heading(&render_inline(trimmed[level..].trim()), '-'));
+        } else if let Some(item) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| trimmed.strip_prefix(bullet))
+        {
+            out.push_str(&format!("{indent}  - {}\n", render_inline(item)));
+        } else {
+            out.push_str(&format!("{indent}{}\n", render_inline(trimmed)));
+        }
+    }
+    out
+}
+
+fn heading(text: &str, underline: char) -> String {
+    let rule = underline.to_string().repeat(text.chars().count());
+    format!("{text}\n{rule}\n")
+}
+
+/// Drops emphasis and code markers and spells out links as `text (url)`.
+fn render_inline(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some(link) = rest.strip_prefix("![").or_else(|| rest.strip_prefix('[')) {
+            if let Some((label, url, after)) = split_link(link) {
+                out.push_str(&render_inline(label));
+                if !url.is_empty() && url != label {
+                    out.push_str(&format!(" ({url})"));
+                }
+                rest = after;
+                continue;
+            }
+        }
+        if let Some(after) = ["**", "__", "~~"]
+            .iter()
+            .find_map(|marker| rest.strip_prefix(marker))
+        {
+            rest = after;
+            continue;
+        }
+        if c != '`' {
+            out.push(c);
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Splits `label](url)rest` into its parts.
+fn split_link(link: &str) -> Option<(&str, &str, &str)> {
+    let (label, after) = link.split_once("](")?;
+    let (url, rest) = after.split_once(')')?;
+    Some((label, url, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let markdown = "## What's Changed\r\n\r\n\r\n* **Fixed** the `denmaps.dll` crash by @dev in [#12](https://example.com/pull/12)\r\n  - nested item\r\n\r\nSee [the docs](https://example.com) ~~or not~~\r\n";
+        assert_eq!(
+            render_markdown(markdown),
+            "What's Changed\n--------------\n\n  - Fixed the denmaps.dll crash by @dev in #12 (https://example.com/pull/12)\n    - nested item\n\nSee the docs (https://example.com) or not\n"
+        );
+    }
+
+    #[test]
+    fn test_render_covers_versions_since_installed() {
+        let release = |tag: &str, body: Option<&str>| Release {
+            tag_name: tag.to_string(),
+            prerelease: false,
+            draft: false,
+            body: body.map(str::to_string),
+            assets: Vec::new(),
+        };
+        let releases = vec![
+            release("v2.0.0", Some("installed")),
+            release("v2.1.0", Some("first")),
+            release("v2.2.0", None),
+            release("v2.3.0", Some("too new")),
+            // Not flagged as a pre-release on GitHub, but still a beta by its version
+            release("v2.1.1-beta.1", Some("beta")),
+        ];
+        assert_eq!(
+            render(&releases, "2.0.0", &releases[2], Channel::Stable),
+            "v2.2.0\n======\nNo release notes.\n\nv2.1.0\n======\nfirst\n\n"
+        );
+        assert_eq!(
+            render(&releases, "2.0.0", &releases[2], Channel::Beta),
+            "v2.2.0\n======\nNo release notes.\n\nv2.1.1-beta.1\n=============\nbeta\n\nv2.1.0\n======\nfirst\n\n"
+        );
+    }
+}
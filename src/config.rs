@@ -0,0 +1,112 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    release_source::SourceConfig,
+    updater::{Channel, UpdateDecision},
+};
+
+const CONFIG_FILE: &str = "denlauncher.json";
+const DATA_DIR: &str = ".denlauncher";
+
+/// Launcher settings persisted next to the executable.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<Channel>,
+    /// How many times a dropped or failed update download is attempted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_attempts: Option<u32>,
+    /// Where releases come from, GitHub unless configured otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceConfig>,
+    /// Release tag to stay on; no updates are offered while it is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned_version: Option<String>,
+    /// Version the user chose to skip; newer releases are still offered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped_version: Option<String>,
+    /// What to do about an update when there is no terminal to ask on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unattended_update: Option<UpdateDecision>,
+    /// Minimum time between two update checks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_check_interval_minutes: Option<u64>,
+    /// How long a launch waits for the background update check before moving on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_check_timeout_secs: Option<u64>,
+    /// Proxy for all update traffic, instead of `HTTPS_PROXY`/`HTTP_PROXY`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Hosts reached without the proxy, instead of `NO_PROXY`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<Vec<String>>,
+    /// PEM files with extra CA certificates to trust, relative to the launcher.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_certificates: Option<Vec<PathBuf>>,
+    /// Directory, e.g. on a network share, where downloaded release archives are shared
+    /// with other machines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_cache: Option<PathBuf>,
+    /// How many save backups to keep.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_backup_count: Option<usize>,
+    /// Save backups older than this are removed, except the newest one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_backup_max_age_days: Option<u64>,
+}
+
+impl Config {
+    pub fn load() -> Config {
+        let path = config_path();
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                tracing::warn!(
+                    "Failed to parse config at {:?}, using defaults: {}",
+                    path,
+                    e
+                );
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        let path = config_path();
+        let contents = serde_json::to_string_pretty(self).expect("Failed to serialize config");
+        std::fs::write(&path, contents)
+            .map_err(|e| tracing::warn!("Failed to write config to {:?}: {}", path, e))
+            .ok();
+    }
+}
+
+pub fn exe_dir() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+        .unwrap_or_default()
+}
+
+fn config_path() -> PathBuf {
+    exe_dir().join(CONFIG_FILE)
+}
+
+/// Directory for launcher state such as partial downloads.
+pub fn data_dir() -> PathBuf {
+    exe_dir().join(DATA_DIR)
+}
+
+/// Replaces `path` with `contents` through a temporary file, so a launcher exiting
+/// halfway leaves either the old or the new file, never a truncated one.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(contents)?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
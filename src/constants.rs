@@ -8,10 +8,6 @@ use windows::Win32::System::Threading::{
     PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE,
 };
 
-pub static REPO_PRIVATE_KEY: LazyLock<String> = LazyLock::new(|| {
-    env::var("REPO_PRIVATE_KEY").unwrap_or_else(|_| dotenv!("REPO_PRIVATE_KEY").to_string())
-});
-
 pub static REPO_OWNER: LazyLock<String> =
     LazyLock::new(|| env::var("REPO_OWNER").unwrap_or_else(|_| dotenv!("REPO_OWNER").to_string()));
 
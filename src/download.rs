@@ -0,0 +1,353 @@
+//! Resumable HTTP downloads used by the updater.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use sha2::{Digest, Sha256};
+
+/// Size of the chunks downloads are streamed to disk in.
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+pub const DEFAULT_DOWNLOAD_ATTEMPTS: u32 = 5;
+
+/// How many times, and how patiently, a failed download is retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn with_attempts(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+
+    /// Delay after the given (1-based) failed attempt, doubling every time.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay)
+    }
+}
+
+#[derive(Debug)]
+enum AttemptError {
+    /// Worth retrying: dropped connections, timeouts, server-side errors.
+    Transient(String),
+    Fatal(String),
+}
+
+/// Downloads into `partial_path`, resuming from whatever an earlier attempt or launch
+/// left there. `make_request` builds a fresh request for every attempt.
+///
+/// Returns the completed file rewound to the start, and the total size the server
+/// announced, if it did.
+pub fn download_resumable(
+    make_request: impl Fn() -> ureq::Request,
+    partial_path: &Path,
+    retry: RetryPolicy,
+) -> Result<(File, Option<u64>), String> {
+    let mut attempt = 1;
+    loop {
+        match download_attempt(&make_request, partial_path) {
+            Ok(download) => return Ok(download),
+            Err(AttemptError::Transient(err)) if attempt < retry.max_attempts => {
+                let delay = retry.delay(attempt);
+                tracing::warn!(
+                    "Download attempt {}/{} failed: {}. Retrying in {:?}",
+                    attempt,
+                    retry.max_attempts,
+                    err,
+                    delay
+                );
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(AttemptError::Transient(err) | AttemptError::Fatal(err)) => return Err(err),
+        }
+    }
+}
+
+fn download_attempt(
+    make_request: &impl Fn() -> ureq::Request,
+    partial_path: &Path,
+) -> Result<(File, Option<u64>), AttemptError> {
+    let fatal = |e: std::io::Error| {
+        AttemptError::Fatal(format!("Failed to access {:?}: {}", partial_path, e))
+    };
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(partial_path)
+        .map_err(fatal)?;
+    let mut offset = file.metadata().map_err(fatal)?.len();
+
+    let mut request = make_request();
+    if offset > 0 {
+        tracing::info!("Resuming download at {} bytes", offset);
+        request = request.set("Range", &format!("bytes={offset}-"));
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(416, _)) => {
+            // The partial file doesn't match what the server has, start over
+            file.set_len(0).map_err(fatal)?;
+            return Err(AttemptError::Transient(
+                "server rejected the resume range".to_string(),
+            ));
+        }
+        Err(ureq::Error::Status(code, _)) if code == 408 || code == 429 || code >= 500 => {
+            return Err(AttemptError::Transient(format!("HTTP {code}")));
+        }
+        Err(ureq::Error::Status(code, _)) => {
+            return Err(AttemptError::Fatal(format!("HTTP {code}")));
+        }
+        Err(err) => return Err(AttemptError::Transient(err.to_string())),
+    };
+
+    match response.status() {
+        206 if content_range_start(&response) == Some(offset) => {}
+        206 => {
+            file.set_len(0).map_err(fatal)?;
+            return Err(AttemptError::Transient(
+                "server resumed at the wrong offset".to_string(),
+            ));
+        }
+        200 => {
+            if offset > 0 {
+                tracing::info!("Server does not support resuming, restarting download");
+            }
+            file.set_len(0).map_err(fatal)?;
+            offset = 0;
+        }
+        status => return Err(AttemptError::Fatal(format!("HTTP {status}"))),
+    }
+
+    let expected = response
+        .header("Content-Length")
+        .and_then(|len| len.parse::<u64>().ok())
+        .map(|len| offset + len);
+
+    let progress = download_progress(expected);
+    progress.set_position(offset);
+    let result = stream_to_file(response.into_reader(), &mut file, &progress);
+    progress.finish();
+    let total = offset + result?;
+
+    if let Some(expected) = expected {
+        if total < expected {
+            return Err(AttemptError::Transient(format!(
+                "connection closed after {total} of {expected} bytes"
+            )));
+        }
+    }
+
+    file.seek(SeekFrom::Start(0)).map_err(fatal)?;
+    Ok((file, expected))
+}
+
+/// First byte position of a `Content-Range: bytes <start>-<end>/<total>` header.
+fn content_range_start(response: &ureq::Response) -> Option<u64> {
+    response
+        .header("Content-Range")?
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn download_progress(total: Option<u64>) -> ProgressBar {
+    let (progress, template) = match total {
+        Some(total) => (
+            ProgressBar::new(total),
+            "{bar:40} {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}",
+        ),
+        None => (
+            ProgressBar::new_spinner(),
+            "{spinner} {bytes} {bytes_per_sec}",
+        ),
+    };
+    progress.set_style(ProgressStyle::with_template(template).expect("Invalid progress template"));
+    progress
+}
+
+/// Appends `reader` to `file` chunk by chunk, returning the number of bytes written.
+/// Read errors are transient (the connection went away), write errors are not.
+fn stream_to_file(
+    mut reader: impl Read,
+    file: &mut File,
+    progress: &ProgressBar,
+) -> Result<u64, AttemptError> {
+    let mut buf = vec![0; DOWNLOAD_CHUNK_SIZE];
+    let mut written = 0;
+
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                file.flush().ok();
+                return Err(AttemptError::Transient(e.to_string()));
+            }
+        };
+        file.write_all(&buf[..read])
+            .map_err(|e| AttemptError::Fatal(format!("Failed to write download: {e}")))?;
+        written += read as u64;
+        progress.inc(read as u64);
+    }
+    file.flush()
+        .map_err(|e| AttemptError::Fatal(format!("Failed to write download: {e}")))?;
+
+    Ok(written)
+}
+
+/// Hex-encoded SHA-256 of the whole file. Leaves the cursor at the start.
+pub fn file_sha256(file: &mut File) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    file.seek(SeekFrom::Start(0))?;
+    std::io::copy(file, &mut hasher)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{serve, TestResponse};
+
+    const NO_DELAY: RetryPolicy = RetryPolicy {
+        max_attempts: 5,
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+    };
+
+    fn payload() -> Vec<u8> {
+        (0..200_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Serves `body` with Range support, dropping every connection after `drop_after` bytes.
+    fn flaky_server(body: Vec<u8>, drop_after: usize) -> crate::test_server::TestServer {
+        serve(move |request, _| {
+            let start = request
+                .header("Range")
+                .and_then(|range| range.strip_prefix("bytes="))
+                .and_then(|range| range.strip_suffix('-'))
+                .map(|start| start.parse::<usize>().unwrap())
+                .unwrap_or(0);
+            let response = TestResponse::ok(body[start..].to_vec()).truncated_at(drop_after);
+            if start == 0 {
+                response
+            } else {
+                TestResponse {
+                    status: 206,
+                    ..response.with_header(
+                        "Content-Range",
+                        &format!("bytes {}-{}/{}", start, body.len() - 1, body.len()),
+                    )
+                }
+            }
+        })
+    }
+
+    fn read_all(mut file: File) -> Vec<u8> {
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        let retry = RetryPolicy::with_attempts(10);
+        assert_eq!(retry.delay(1), Duration::from_secs(1));
+        assert_eq!(retry.delay(2), Duration::from_secs(2));
+        assert_eq!(retry.delay(4), Duration::from_secs(8));
+        assert_eq!(retry.delay(9), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_download_resumes_after_dropped_connections() {
+        let body = payload();
+        let server = flaky_server(body.clone(), 64 * 1024);
+        let dir = tempfile::TempDir::new().unwrap();
+        let partial = dir.path().join("den.zip.part");
+
+        let url = format!("{}/den.zip", server.url());
+        let (file, expected) = download_resumable(|| ureq::get(&url), &partial, NO_DELAY).unwrap();
+
+        assert_eq!(expected, Some(body.len() as u64));
+        assert_eq!(read_all(file), body);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].header("Range"), None);
+        assert_eq!(requests[1].header("Range"), Some("bytes=65536-"));
+    }
+
+    #[test]
+    fn test_download_gives_up_after_max_attempts() {
+        let server = flaky_server(payload(), 1024);
+        let dir = tempfile::TempDir::new().unwrap();
+        let partial = dir.path().join("den.zip.part");
+
+        let url = format!("{}/den.zip", server.url());
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            ..NO_DELAY
+        };
+        assert!(download_resumable(|| ureq::get(&url), &partial, retry).is_err());
+        assert_eq!(server.requests().len(), 3);
+
+        // The partial download survives for the next launch
+        assert_eq!(std::fs::metadata(&partial).unwrap().len(), 3 * 1024);
+    }
+
+    #[test]
+    fn test_download_restarts_when_range_is_ignored() {
+        let body = payload();
+        let served = body.clone();
+        let server = serve(move |_, _| TestResponse::ok(served.clone()));
+        let dir = tempfile::TempDir::new().unwrap();
+        let partial = dir.path().join("den.zip.part");
+        std::fs::write(&partial, b"stale bytes").unwrap();
+
+        let url = format!("{}/den.zip", server.url());
+        let (file, _) = download_resumable(|| ureq::get(&url), &partial, NO_DELAY).unwrap();
+        assert_eq!(read_all(file), body);
+    }
+
+    #[test]
+    fn test_download_does_not_retry_client_errors() {
+        let server = serve(|_, _| TestResponse::status(404));
+        let dir = tempfile::TempDir::new().unwrap();
+        let partial = dir.path().join("den.zip.part");
+
+        let url = format!("{}/den.zip", server.url());
+        assert!(download_resumable(|| ureq::get(&url), &partial, NO_DELAY).is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_file_sha256() {
+        let data = payload();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&data).unwrap();
+        assert_eq!(
+            file_sha256(&mut file).unwrap(),
+            format!("{:x}", Sha256::digest(&data))
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Extraction of release archives. Every entry is validated before anything is written,
+//! so even a validly signed but badly built archive can't write outside the target dir.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use zip::ZipArchive;
+
+/// Bounds on what an archive may unpack to.
+#[derive(Clone, Copy, Debug)]
+pub struct ExtractLimits {
+    pub max_entries: usize,
+    /// Total uncompressed size of all entries, in bytes.
+    pub max_total_size: u64,
+}
+
+pub const DEFAULT_LIMITS: ExtractLimits = ExtractLimits {
+    max_entries: 10_000,
+    max_total_size: 2 * 1024 * 1024 * 1024,
+};
+
+/// Extracts `archive` into `dest`. Fails without writing anything if an entry has an
+/// absolute or escaping path, is a symlink, or repeats an earlier name, and stops as
+/// soon as the limits are exceeded.
+pub fn extract_archive(archive: &File, dest: &Path, limits: ExtractLimits) -> Result<(), String> {
+    let mut zip = ZipArchive::new(archive).map_err(|e| format!("Failed to open archive: {e}"))?;
+    if zip.len() > limits.max_entries {
+        return Err(format!(
+            "archive has {} entries, more than the limit of {}",
+            zip.len(),
+            limits.max_entries
+        ));
+    }
+
+    // ZipArchive indexes entries by name, so entries repeating a name exactly only show
+    // up as fewer entries than the archive declares
+    if declared_entry_count(archive).is_some_and(|count| count != zip.len()) {
+        return Err("archive has entries with the same name".to_string());
+    }
+
+    let mut entries = Vec::with_capacity(zip.len());
+    let mut seen = HashSet::new();
+    let mut declared_size = 0u64;
+    for index in 0..zip.len() {
+        let entry = zip
+            .by_index(index)
+            .map_err(|e| format!("Failed to read archive entry {index}: {e}"))?;
+        let name = entry.name().to_string();
+        if entry.is_symlink() {
+            return Err(format!("archive entry {name:?} is a symlink"));
+        }
+        let path = entry_path(&name)
+            .ok_or_else(|| format!("archive entry {name:?} has an unsafe path"))?;
+        // Windows paths are case-insensitive, so `a.dll` and `A.dll` collide
+        if !seen.insert(path.to_string_lossy().to_lowercase()) {
+            return Err(format!("archive entry {name:?} appears more than once"));
+        }
+        declared_size = declared_size.saturating_add(entry.size());
+        entries.push((index, path, entry.is_dir()));
+    }
+    if declared_size > limits.max_total_size {
+        return Err(format!(
+            "archive unpacks to {declared_size} bytes, more than the limit of {}",
+            limits.max_total_size
+        ));
+    }
+
+    // Sizes in the archive are only claims, so the limit is enforced on actual output too
+    let mut remaining = limits.max_total_size;
+    for (index, path, is_dir) in entries {
+        let target = dest.join(&path);
+        let write_error = |e: io::Error| format!("Failed to extract {:?}: {}", path, e);
+        if is_dir {
+            fs::create_dir_all(&target).map_err(write_error)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(write_error)?;
+        }
+
+        let entry = zip
+            .by_index(index)
+            .map_err(|e| format!("Failed to read archive entry {index}: {e}"))?;
+        let mut out = File::create(&target).map_err(write_error)?;
+        let written = io::copy(&mut entry.take(remaining + 1), &mut out).map_err(write_error)?;
+        if written > remaining {
+            return Err(format!(
+                "archive unpacks to more than the limit of {} bytes",
+                limits.max_total_size
+            ));
+        }
+        remaining -= written;
+    }
+    Ok(())
+}
+
+/// Entry count from the end of central directory record, if it can be found and the
+/// archive isn't zip64.
+fn declared_entry_count(mut archive: &File) -> Option<usize> {
+    const EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+    // The record is 22 bytes, followed by a comment of up to 64 KiB
+    let tail_len = archive.metadata().ok()?.len().min(22 + 0xFFFF);
+    let mut tail = vec![0; tail_len as usize];
+    archive.seek(SeekFrom::End(-(tail_len as i64))).ok()?;
+    archive.read_exact(&mut tail).ok()?;
+    archive.rewind().ok()?;
+
+    let eocd = tail
+        .windows(EOCD_SIGNATURE.len())
+        .rposition(|window| window == EOCD_SIGNATURE)?;
+    let count = u16::from_le_bytes(tail.get(eocd + 10..eocd + 12)?.try_into().ok()?);
+    (count != u16::MAX).then_some(count as usize)
+}
+
+/// Relative path for an entry name, or `None` if it is absolute, has a drive or root,
+/// or contains `.` or `..`. Both `/` and `\` count as separators, as on Windows.
+fn entry_path(name: &str) -> Option<PathBuf> {
+    let segments: Vec<&str> = name
+        .trim_end_matches(['/', '\\'])
+        .split(['/', '\\'])
+        .collect();
+    let safe = segments
+        .iter()
+        .all(|s| !s.is_empty() && *s != "." && *s != ".." && !s.contains(':'));
+    safe.then(|| segments.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_archive;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn extract(zip: &[u8], limits: ExtractLimits) -> (tempfile::TempDir, Result<(), String>) {
+        let mut archive = tempfile::tempfile().unwrap();
+        archive.write_all(zip).unwrap();
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join("out");
+        fs::create_dir(&dest).unwrap();
+        let result = extract_archive(&archive, &dest, limits);
+        (dir, result)
+    }
+
+    #[test]
+    fn test_extracts_release() {
+        let zip = test_archive::zip_bytes(&[
+            ("DEN-Launcher.exe", b"exe"),
+            ("DENData/denmaps.dll", b"dll"),
+        ]);
+        let zip = test_archive::sign_zip(&zip, &test_archive::test_key(1), "DEN.zip");
+        let (dir, result) = extract(&zip, DEFAULT_LIMITS);
+        result.unwrap();
+        let out = dir.path().join("out");
+        assert_eq!(fs::read(out.join("DENData/denmaps.dll")).unwrap(), b"dll");
+        assert_eq!(fs::read(out.join("DEN-Launcher.exe")).unwrap(), b"exe");
+    }
+
+    #[test]
+    fn test_rejects_escaping_paths() {
+        for name in [
+            "../evil.dll",
+            "DENData/../../evil.dll",
+            "/evil.dll",
+            "..\\evil.dll",
+            "C:\\Windows\\evil.dll",
+        ] {
+            let zip = test_archive::zip_bytes(&[("DENData/denmaps.dll", b"dll"), (name, b"evil")]);
+            let (dir, result) = extract(&zip, DEFAULT_LIMITS);
+            assert!(result.is_err(), "{name} was extracted");
+            // Validation happens before anything is written
+            assert!(!dir.path().join("out/DENData").exists());
+            assert!(!dir.path().join("evil.dll").exists());
+        }
+    }
+
+    #[test]
+    fn test_rejects_symlinks() {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        writer
+            .add_symlink("DENData", "/etc", SimpleFileOptions::default())
+            .unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+        assert!(extract(&zip, DEFAULT_LIMITS).1.is_err());
+    }
+
+    #[test]
+    fn test_rejects_duplicate_names() {
+        let zip = test_archive::zip_bytes(&[("denmaps.dll", b"one"), ("DENMAPS.dll", b"two")]);
+        assert!(extract(&zip, DEFAULT_LIMITS).1.is_err());
+
+        // Identical names can't be written with ZipWriter, so patch the second one in
+        let zip = test_archive::zip_bytes(&[("denmaps.dll", b"one"), ("denmaps.xxx", b"two")]);
+        let zip = replace_all(&zip, b"denmaps.xxx", b"denmaps.dll");
+        assert!(extract(&zip, DEFAULT_LIMITS).1.is_err());
+    }
+
+    fn replace_all(haystack: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+        let mut out = haystack.to_vec();
+        let mut i = 0;
+        while i + from.len() <= out.len() {
+            if &out[i..i + from.len()] == from {
+                out[i..i + from.len()].copy_from_slice(to);
+            }
+            i += 1;
+        }
+        out
+    }
+
+    #[test]
+    fn test_enforces_limits() {
+        let zip = test_archive::zip_bytes(&[("a", b"1"), ("b", b"2"), ("c", b"3")]);
+        let limits = ExtractLimits {
+            max_entries: 2,
+            ..DEFAULT_LIMITS
+        };
+        assert!(extract(&zip, limits).1.is_err());
+
+        // Highly compressible, like a zip bomb
+        let zip = test_archive::zip_bytes(&[("bomb.bin", &vec![0; 1024 * 1024])]);
+        let limits = ExtractLimits {
+            max_total_size: 64 * 1024,
+            ..DEFAULT_LIMITS
+        };
+        let (dir, result) = extract(&zip, limits);
+        assert!(result.is_err());
+        assert!(!dir.path().join("out/bomb.bin").exists());
+    }
+}
@@ -67,6 +67,13 @@ pub fn get_pids_by_name(name: &str) -> Vec<u32> {
         .collect()
 }
 
+/// Blocks until no `eldenring.exe` is running anymore.
+pub fn wait_for_game_exit() {
+    while !get_pids_by_name(ELDENRING_EXE).is_empty() {
+        std::thread::sleep(std::time::Duration::from_secs(5));
+    }
+}
+
 pub fn start_game() -> Result<(), Box<dyn std::error::Error>> {
     // Kill existing processes
     for pid in get_pids_by_name(ELDENRING_EXE) {
@@ -0,0 +1,481 @@
+//! Transactional installation of an extracted release.
+//!
+//! A release is first staged under the launcher's data dir, then swapped into the
+//! install directory entry by entry. Everything it replaces is moved into a backup,
+//! which doubles as the last-known-good install for `--rollback`. If any step of the
+//! swap fails, the entries already swapped are moved back.
+//!
+//! Each install records a manifest of the files it wrote. Files the previous install
+//! owned that the new one no longer ships are removed, unless they were edited since.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::config::data_dir;
+use crate::manifest::Manifest;
+
+const STAGING_DIR: &str = "staging";
+const BACKUP_DIR: &str = "backup";
+/// Backup being built during a swap, promoted to `BACKUP_DIR` once the swap succeeds.
+const PENDING_BACKUP_DIR: &str = "backup.pending";
+const ROLLBACK_DIR: &str = "rollback";
+/// Records which launcher version the backup holds.
+const VERSION_FILE: &str = "den-version";
+/// Manifest of the files the install owns, kept in the data dir, the staging tree and
+/// the backup.
+const MANIFEST_FILE: &str = "den-manifest.json";
+
+pub struct Install {
+    exe_dir: PathBuf,
+    exe_path: PathBuf,
+    data_dir: PathBuf,
+}
+
+impl Install {
+    pub fn new(exe_path: PathBuf, data_dir: PathBuf) -> Install {
+        let exe_dir = exe_path
+            .parent()
+            .expect("Executable has no parent dir")
+            .to_path_buf();
+        Install {
+            exe_dir,
+            exe_path,
+            data_dir,
+        }
+    }
+
+    /// The install the running launcher belongs to.
+    pub fn current() -> Install {
+        let exe_path = std::env::current_exe().expect("Failed to get current exe path");
+        Install::new(exe_path, data_dir())
+    }
+
+    pub fn exe_path(&self) -> &Path {
+        &self.exe_path
+    }
+
+    fn exe_name(&self) -> &OsStr {
+        self.exe_path
+            .file_name()
+            .expect("Executable has no file name")
+    }
+
+    fn staging_dir(&self) -> PathBuf {
+        self.data_dir.join(STAGING_DIR)
+    }
+
+    fn backup_dir(&self) -> PathBuf {
+        self.data_dir.join(BACKUP_DIR)
+    }
+
+    fn pending_backup_dir(&self) -> PathBuf {
+        self.data_dir.join(PENDING_BACKUP_DIR)
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.data_dir.join(MANIFEST_FILE)
+    }
+
+    /// Builds the staging tree from an extracted release. Each directory the release
+    /// ships is merged over a copy of the installed one, so files the release doesn't
+    /// ship (user files in the content dir, for one) carry over, except files the
+    /// current install owned that the release dropped.
+    pub fn stage(&self, extracted: &Path) -> io::Result<()> {
+        let staging = self.staging_dir();
+        remove_dir_if_exists(&staging)?;
+        fs::create_dir_all(&staging)?;
+
+        for entry in fs::read_dir(extracted)? {
+            let entry = entry?;
+            let target = staging.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                let installed = self.exe_dir.join(entry.file_name());
+                if installed.is_dir() {
+                    copy_tree(&installed, &target)?;
+                }
+                copy_tree(&entry.path(), &target)?;
+            } else {
+                fs::copy(entry.path(), &target)?;
+            }
+        }
+
+        let manifest = Manifest::build(extracted)?;
+        self.remove_dropped_files(&staging, &manifest)?;
+        manifest.save(&staging.join(MANIFEST_FILE))
+    }
+
+    /// Removes files from the staging tree that the current install owned but
+    /// `manifest` doesn't list. Top-level files are left to `swap_in`.
+    fn remove_dropped_files(&self, staging: &Path, manifest: &Manifest) -> io::Result<()> {
+        let Some(installed) = Manifest::load(&self.manifest_path()) else {
+            return Ok(());
+        };
+        for entry in installed.dropped_by(manifest) {
+            let Some(path) = entry.relative_path() else {
+                continue;
+            };
+            let mut components = path.components();
+            let Some(top) = components.next() else {
+                continue;
+            };
+            if components.next().is_none() {
+                continue;
+            }
+
+            // A directory the release no longer ships at all is staged as installed,
+            // minus the dropped files
+            let staged_top = staging.join(top);
+            let installed_top = self.exe_dir.join(top);
+            if !staged_top.exists() && installed_top.is_dir() {
+                copy_tree(&installed_top, &staged_top)?;
+            }
+
+            let staged = staging.join(&path);
+            if entry.matches(&staged) {
+                tracing::info!(
+                    "Removing {} which the new release no longer ships",
+                    entry.path
+                );
+                fs::remove_file(&staged)?;
+            } else if staged.exists() {
+                tracing::warn!(
+                    "Keeping {} which was changed since it was installed",
+                    entry.path
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Top-level files the current install owned that `source` no longer ships.
+    fn dropped_top_level_files(&self, source: &Path) -> Vec<std::ffi::OsString> {
+        let (Some(installed), Some(incoming)) = (
+            Manifest::load(&self.manifest_path()),
+            Manifest::load(&source.join(MANIFEST_FILE)),
+        ) else {
+            return Vec::new();
+        };
+        installed
+            .dropped_by(&incoming)
+            .filter_map(|entry| {
+                let path = entry.relative_path()?;
+                let name = path.file_name()?;
+                let is_top_level = path.parent() == Some(Path::new(""));
+                (is_top_level && name != self.exe_name() && entry.matches(&self.exe_dir.join(name)))
+                    .then(|| name.to_os_string())
+            })
+            .collect()
+    }
+
+    /// Swaps the staged release into place. On failure the previous install is restored.
+    pub fn commit(&self, installed_version: &str) -> Result<(), String> {
+        let staging = self.staging_dir();
+        let ships_exe = staging.join(self.exe_name()).exists();
+        self.swap_in(&staging, installed_version)?;
+        remove_dir_if_exists(&staging)
+            .map_err(|e| tracing::warn!("Failed to clean up staging dir: {}", e))
+            .ok();
+
+        if !ships_exe {
+            // The release no longer ships a launcher under this name
+            self.delete_exe()
+                .map_err(|e| format!("Failed to delete updater: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Restores the last-known-good install and returns its version. The install being
+    /// replaced becomes the new backup, so a second rollback undoes the first.
+    pub fn rollback(&self, installed_version: &str) -> Result<String, String> {
+        let backup = self.backup_dir();
+        if !backup.is_dir() {
+            return Err("No previous install to roll back to".to_string());
+        }
+        let version = fs::read_to_string(backup.join(VERSION_FILE))
+            .map(|v| v.trim().to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+
+        let source = self.data_dir.join(ROLLBACK_DIR);
+        remove_dir_if_exists(&source).map_err(|e| e.to_string())?;
+        fs::rename(&backup, &source).map_err(|e| format!("Failed to read backup: {e}"))?;
+
+        match self.swap_in(&source, installed_version) {
+            Ok(()) => {
+                remove_dir_if_exists(&source).ok();
+                Ok(version)
+            }
+            Err(err) => {
+                // Put the last-known-good install back where the next attempt expects it
+                remove_dir_if_exists(&backup).ok();
+                fs::rename(&source, &backup).ok();
+                Err(err)
+            }
+        }
+    }
+
+    /// Moves every entry of `source` into the install dir, moving what it replaces into
+    /// a fresh backup, along with top-level files the install owned that `source` drops.
+    /// The executable is replaced last, after everything else succeeded. The previous
+    /// backup is only dropped once the swap went through.
+    fn swap_in(&self, source: &Path, installed_version: &str) -> Result<(), String> {
+        let backup = self.pending_backup_dir();
+        let prepare = || -> io::Result<()> {
+            remove_dir_if_exists(&backup)?;
+            fs::create_dir_all(&backup)?;
+            fs::copy(&self.exe_path, backup.join(self.exe_name()))?;
+            if self.manifest_path().exists() {
+                fs::copy(self.manifest_path(), backup.join(MANIFEST_FILE))?;
+            }
+            fs::write(backup.join(VERSION_FILE), installed_version)
+        };
+        prepare().map_err(|e| format!("Failed to back up current install: {e}"))?;
+
+        let dropped = self.dropped_top_level_files(source);
+
+        let mut swapped = Vec::new();
+        let mut swap = || -> io::Result<()> {
+            for entry in fs::read_dir(source)? {
+                let name = entry?.file_name();
+                if name == self.exe_name() || name == VERSION_FILE || name == MANIFEST_FILE {
+                    continue;
+                }
+                let installed = self.exe_dir.join(&name);
+                let had_installed = installed.symlink_metadata().is_ok();
+                if had_installed {
+                    fs::rename(&installed, backup.join(&name))?;
+                }
+                swapped.push((name.clone(), had_installed));
+                fs::rename(source.join(&name), &installed)?;
+            }
+
+            for name in &dropped {
+                tracing::info!("Removing {:?} which the new release no longer ships", name);
+                fs::rename(self.exe_dir.join(name), backup.join(name))?;
+                swapped.push((name.clone(), true));
+            }
+
+            let new_exe = source.join(self.exe_name());
+            if new_exe.exists() {
+                self.replace_exe(&new_exe)?;
+            }
+            Ok(())
+        };
+
+        if let Err(err) = swap() {
+            tracing::error!("Install failed, restoring previous version: {}", err);
+            self.undo(source, &swapped);
+            remove_dir_if_exists(&backup).ok();
+            return Err(err.to_string());
+        }
+
+        let manifest = source.join(MANIFEST_FILE);
+        let recorded = if manifest.exists() {
+            fs::rename(&manifest, self.manifest_path())
+        } else {
+            // Installs from before manifests were recorded own nothing
+            fs::remove_file(self.manifest_path()).or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            })
+        };
+        recorded
+            .map_err(|e| tracing::warn!("Failed to record installed files: {}", e))
+            .ok();
+
+        remove_dir_if_exists(&self.backup_dir())
+            .and_then(|_| fs::rename(&backup, self.backup_dir()))
+            .map_err(|e| tracing::warn!("Failed to keep backup of previous install: {}", e))
+            .ok();
+        Ok(())
+    }
+
+    fn undo(&self, source: &Path, swapped: &[(std::ffi::OsString, bool)]) {
+        let backup = self.pending_backup_dir();
+        for (name, had_installed) in swapped.iter().rev() {
+            let installed = self.exe_dir.join(name);
+            if installed.symlink_metadata().is_ok() {
+                fs::rename(&installed, source.join(name))
+                    .map_err(|e| tracing::error!("Failed to remove {:?}: {}", installed, e))
+                    .ok();
+            }
+            if *had_installed {
+                fs::rename(backup.join(name), &installed)
+                    .map_err(|e| tracing::error!("Failed to restore {:?}: {}", installed, e))
+                    .ok();
+            }
+        }
+    }
+
+    fn is_running_exe(&self) -> bool {
+        std::env::current_exe().is_ok_and(|exe| exe == self.exe_path)
+    }
+
+    fn replace_exe(&self, new_exe: &Path) -> io::Result<()> {
+        if self.is_running_exe() {
+            tracing::info!("Replacing binary with new version");
+            self_replace::self_replace(new_exe)
+        } else {
+            fs::copy(new_exe, &self.exe_path).map(|_| ())
+        }
+    }
+
+    fn delete_exe(&self) -> io::Result<()> {
+        if self.is_running_exe() {
+            self_replace::self_delete()
+        } else {
+            fs::remove_file(&self.exe_path)
+        }
+    }
+}
+
+fn remove_dir_if_exists(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Copies `from` into `to`, overwriting files that exist in both.
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in WalkDir::new(from) {
+        let entry = entry?;
+        let target = to.join(
+            entry
+                .path()
+                .strip_prefix(from)
+                .expect("Failed to strip prefix"),
+        );
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        _root: tempfile::TempDir,
+        install: Install,
+        exe_dir: PathBuf,
+        extracted: PathBuf,
+    }
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    /// An install of "old" with a user file, and an extracted "new" release.
+    fn fixture() -> Fixture {
+        let root = tempfile::TempDir::new().unwrap();
+        let exe_dir = root.path().join("game");
+        let extracted = root.path().join("extracted");
+
+        write(&exe_dir.join("DEN-Launcher.exe"), "old exe");
+        write(&exe_dir.join("DENData/denmaps.dll"), "old dll");
+        write(&exe_dir.join("DENData/user.txt"), "user file");
+
+        write(&extracted.join("DEN-Launcher.exe"), "new exe");
+        write(&extracted.join("DENData/denmaps.dll"), "new dll");
+
+        let install = Install::new(
+            exe_dir.join("DEN-Launcher.exe"),
+            exe_dir.join(".denlauncher"),
+        );
+        Fixture {
+            _root: root,
+            install,
+            exe_dir,
+            extracted,
+        }
+    }
+
+    #[test]
+    fn test_commit_and_rollback() {
+        let f = fixture();
+        f.install.stage(&f.extracted).unwrap();
+        f.install.commit("1.0.0").unwrap();
+
+        assert_eq!(read(&f.exe_dir.join("DEN-Launcher.exe")), "new exe");
+        assert_eq!(read(&f.exe_dir.join("DENData/denmaps.dll")), "new dll");
+        assert_eq!(read(&f.exe_dir.join("DENData/user.txt")), "user file");
+        assert!(!f.install.staging_dir().exists());
+
+        assert_eq!(f.install.rollback("1.1.0").unwrap(), "1.0.0");
+        assert_eq!(read(&f.exe_dir.join("DEN-Launcher.exe")), "old exe");
+        assert_eq!(read(&f.exe_dir.join("DENData/denmaps.dll")), "old dll");
+        assert_eq!(read(&f.exe_dir.join("DENData/user.txt")), "user file");
+
+        // Rolling back again returns to the version we rolled back from
+        assert_eq!(f.install.rollback("1.0.0").unwrap(), "1.1.0");
+        assert_eq!(read(&f.exe_dir.join("DENData/denmaps.dll")), "new dll");
+    }
+
+    #[test]
+    fn test_failed_commit_restores_previous_install() {
+        let f = fixture();
+        f.install.stage(&f.extracted).unwrap();
+
+        // A directory where the new exe should be makes the final step fail
+        let staged_exe = f.install.staging_dir().join("DEN-Launcher.exe");
+        fs::remove_file(&staged_exe).unwrap();
+        fs::create_dir(&staged_exe).unwrap();
+
+        assert!(f.install.commit("1.0.0").is_err());
+        assert_eq!(read(&f.exe_dir.join("DEN-Launcher.exe")), "old exe");
+        assert_eq!(read(&f.exe_dir.join("DENData/denmaps.dll")), "old dll");
+        assert_eq!(read(&f.exe_dir.join("DENData/user.txt")), "user file");
+        assert!(!f.install.backup_dir().exists());
+    }
+
+    #[test]
+    fn test_update_removes_dropped_files() {
+        let f = fixture();
+        write(&f.extracted.join("DENData/old.dll"), "old dll");
+        write(&f.extracted.join("DENData/edited.txt"), "shipped");
+        write(&f.extracted.join("readme.txt"), "readme");
+        f.install.stage(&f.extracted).unwrap();
+        f.install.commit("1.0.0").unwrap();
+        write(&f.exe_dir.join("DENData/edited.txt"), "edited by user");
+
+        let next = f.extracted.with_file_name("next");
+        write(&next.join("DEN-Launcher.exe"), "next exe");
+        write(&next.join("DENData/denmaps.dll"), "next dll");
+        f.install.stage(&next).unwrap();
+        f.install.commit("1.1.0").unwrap();
+
+        assert_eq!(read(&f.exe_dir.join("DENData/denmaps.dll")), "next dll");
+        assert!(!f.exe_dir.join("DENData/old.dll").exists());
+        assert!(!f.exe_dir.join("readme.txt").exists());
+        assert_eq!(
+            read(&f.exe_dir.join("DENData/edited.txt")),
+            "edited by user"
+        );
+        assert_eq!(read(&f.exe_dir.join("DENData/user.txt")), "user file");
+
+        // Rolling back brings the dropped files back
+        f.install.rollback("1.2.0").unwrap();
+        assert_eq!(read(&f.exe_dir.join("DENData/old.dll")), "old dll");
+        assert_eq!(read(&f.exe_dir.join("readme.txt")), "readme");
+    }
+
+    #[test]
+    fn test_rollback_without_backup() {
+        let f = fixture();
+        assert!(f.install.rollback("1.0.0").is_err());
+        assert_eq!(read(&f.exe_dir.join("DENData/denmaps.dll")), "old dll");
+    }
+}
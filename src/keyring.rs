@@ -0,0 +1,364 @@
+//! Release signing keys the launcher trusts.
+//!
+//! Keys are embedded at build time. More are learned from key statements: a trusted key
+//! endorsing a new key, or revoking one. Statements ship with releases as a
+//! `keyring.json` asset and are kept in the data dir. They are re-verified on every
+//! load, so editing the stored file can't make the launcher trust a key.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH};
+use serde::{Deserialize, Serialize};
+
+use crate::config::data_dir;
+
+/// Name of the release asset carrying key statements.
+pub const KEYRING_ASSET: &str = "keyring.json";
+const KEYRING_FILE: &str = "keyring.json";
+
+pub type PublicKey = [u8; PUBLIC_KEY_LENGTH];
+
+const EMBEDDED_KEYS: &[PublicKey] = &[*include_bytes!("../release_public_key.bin")];
+
+/// Hex-encoded keys that are never trusted, whatever endorses them.
+const REVOKED_KEYS: &[&str] = &[];
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementKind {
+    Endorse,
+    Revoke,
+}
+
+impl fmt::Display for StatementKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StatementKind::Endorse => "endorse",
+            StatementKind::Revoke => "revoke",
+        })
+    }
+}
+
+/// `signer` endorses or revokes `key`. The signature is a plain Ed25519 signature by
+/// `signer` over `den-keyring-v1 <kind> <key>`, with keys hex-encoded.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct KeyStatement {
+    pub kind: StatementKind,
+    pub key: String,
+    pub signer: String,
+    pub signature: String,
+}
+
+impl KeyStatement {
+    pub fn message(kind: StatementKind, key: &PublicKey) -> String {
+        format!("den-keyring-v1 {} {}", kind, to_hex(key))
+    }
+
+    fn key(&self) -> Option<PublicKey> {
+        from_hex(&self.key)?.try_into().ok()
+    }
+
+    fn signer(&self) -> Option<PublicKey> {
+        from_hex(&self.signer)?.try_into().ok()
+    }
+
+    fn is_valid(&self) -> bool {
+        let (Some(key), Some(signer)) = (self.key(), self.signer()) else {
+            return false;
+        };
+        let Some(signature) = from_hex(&self.signature)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(|bytes| Signature::from_bytes(&bytes))
+        else {
+            return false;
+        };
+        VerifyingKey::from_bytes(&signer).is_ok_and(|signer| {
+            signer
+                .verify_strict(Self::message(self.kind, &key).as_bytes(), &signature)
+                .is_ok()
+        })
+    }
+}
+
+/// Layout of both the release asset and the stored file.
+#[derive(Deserialize, Serialize, Default)]
+pub struct KeyStatements {
+    pub statements: Vec<KeyStatement>,
+}
+
+pub struct Keyring {
+    embedded: Vec<PublicKey>,
+    revoked: Vec<PublicKey>,
+    statements: Vec<KeyStatement>,
+    trusted: Vec<PublicKey>,
+}
+
+impl Keyring {
+    pub fn new(
+        embedded: Vec<PublicKey>,
+        revoked: Vec<PublicKey>,
+        statements: Vec<KeyStatement>,
+    ) -> Keyring {
+        let mut keyring = Keyring {
+            embedded,
+            revoked,
+            statements,
+            trusted: Vec::new(),
+        };
+        keyring.resolve();
+        keyring
+    }
+
+    /// The embedded keys plus everything learned from stored statements.
+    pub fn load() -> Keyring {
+        let revoked = REVOKED_KEYS
+            .iter()
+            .map(|key| {
+                from_hex(key)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .expect("Invalid revoked key")
+            })
+            .collect();
+        let statements = std::fs::read_to_string(keyring_path())
+            .ok()
+            .and_then(|contents| {
+                serde_json::from_str::<KeyStatements>(&contents)
+                    .map_err(|e| tracing::warn!("Ignoring unreadable keyring: {}", e))
+                    .ok()
+            })
+            .unwrap_or_default()
+            .statements;
+        Keyring::new(EMBEDDED_KEYS.to_vec(), revoked, statements)
+    }
+
+    pub fn save(&self) {
+        let contents = serde_json::to_string_pretty(&KeyStatements {
+            statements: self.statements.clone(),
+        })
+        .expect("Failed to serialize keyring");
+        let path = keyring_path();
+        path.parent()
+            .map(std::fs::create_dir_all)
+            .transpose()
+            .and_then(|_| std::fs::write(&path, contents))
+            .map_err(|e| tracing::warn!("Failed to write keyring to {:?}: {}", path, e))
+            .ok();
+    }
+
+    /// Keys currently trusted to sign releases.
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.trusted
+    }
+
+    /// Adds statements not seen before. Returns whether any were new.
+    pub fn add_statements(&mut self, statements: Vec<KeyStatement>) -> bool {
+        let before = self.statements.len();
+        for statement in statements {
+            if !self.statements.contains(&statement) {
+                self.statements.push(statement);
+            }
+        }
+        if self.statements.len() == before {
+            return false;
+        }
+
+        let previous = self.trusted.clone();
+        self.resolve();
+        for key in self.trusted.iter().filter(|key| !previous.contains(key)) {
+            tracing::info!("Now trusting release key {}", fingerprint(key));
+        }
+        for key in previous.iter().filter(|key| !self.trusted.contains(key)) {
+            tracing::warn!("Release key {} has been revoked", fingerprint(key));
+        }
+        true
+    }
+
+    /// Applies valid statements by trusted signers until nothing changes, so chains of
+    /// endorsements resolve in any order. Revocations are permanent and win over
+    /// endorsements: whenever keys are revoked, trust is worked out again from the
+    /// embedded keys, dropping whatever the revoked keys endorsed.
+    fn resolve(&mut self) {
+        let mut revoked: HashSet<PublicKey> = self.revoked.iter().copied().collect();
+        let valid: Vec<(&KeyStatement, PublicKey, PublicKey)> = self
+            .statements
+            .iter()
+            .filter(|statement| statement.is_valid())
+            .filter_map(|statement| Some((statement, statement.key()?, statement.signer()?)))
+            .collect();
+
+        loop {
+            let mut trusted: Vec<PublicKey> = self
+                .embedded
+                .iter()
+                .filter(|key| !revoked.contains(*key))
+                .copied()
+                .collect();
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for (statement, key, signer) in &valid {
+                    if statement.kind == StatementKind::Endorse
+                        && trusted.contains(signer)
+                        && !revoked.contains(key)
+                        && !trusted.contains(key)
+                    {
+                        trusted.push(*key);
+                        changed = true;
+                    }
+                }
+            }
+
+            let newly_revoked: Vec<PublicKey> = valid
+                .iter()
+                .filter(|(statement, key, signer)| {
+                    statement.kind == StatementKind::Revoke
+                        && trusted.contains(signer)
+                        && !revoked.contains(key)
+                })
+                .map(|(_, key, _)| *key)
+                .collect();
+            if newly_revoked.is_empty() {
+                self.trusted = trusted;
+                return;
+            }
+            revoked.extend(newly_revoked);
+        }
+    }
+}
+
+/// Short, log-friendly identifier for a key.
+pub fn fingerprint(key: &PublicKey) -> String {
+    to_hex(&key[..8])
+}
+
+pub fn parse_statements(contents: &str) -> Result<Vec<KeyStatement>, String> {
+    serde_json::from_str::<KeyStatements>(contents)
+        .map(|file| file.statements)
+        .map_err(|e| format!("Invalid key statements: {e}"))
+}
+
+fn keyring_path() -> PathBuf {
+    data_dir().join(KEYRING_FILE)
+}
+
+/// Statements shipped next to an archive on disk, for offline installs.
+pub fn statements_beside(archive: &Path) -> Option<Vec<KeyStatement>> {
+    let contents = std::fs::read_to_string(archive.with_file_name(KEYRING_ASSET)).ok()?;
+    parse_statements(&contents)
+        .map_err(|e| tracing::warn!("{}", e))
+        .ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 == 1 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_archive::{public_key, test_key};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn statement(kind: StatementKind, key: &SigningKey, signer: &SigningKey) -> KeyStatement {
+        let key = public_key(key);
+        let signature = signer.sign(KeyStatement::message(kind, &key).as_bytes());
+        KeyStatement {
+            kind,
+            key: to_hex(&key),
+            signer: to_hex(&public_key(signer)),
+            signature: to_hex(&signature.to_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_endorsement_chain() {
+        let (old, new, newest) = (test_key(1), test_key(2), test_key(3));
+        // Listed out of order, the chain still resolves
+        let keyring = Keyring::new(
+            vec![public_key(&old)],
+            Vec::new(),
+            vec![
+                statement(StatementKind::Endorse, &newest, &new),
+                statement(StatementKind::Endorse, &new, &old),
+            ],
+        );
+        assert_eq!(
+            keyring.keys(),
+            [public_key(&old), public_key(&new), public_key(&newest)]
+        );
+    }
+
+    #[test]
+    fn test_untrusted_or_forged_statements_are_ignored() {
+        let (trusted, stranger, other) = (test_key(1), test_key(2), test_key(3));
+        let mut forged = statement(StatementKind::Endorse, &other, &trusted);
+        forged.key = to_hex(&public_key(&stranger));
+
+        let keyring = Keyring::new(
+            vec![public_key(&trusted)],
+            Vec::new(),
+            vec![statement(StatementKind::Endorse, &other, &stranger), forged],
+        );
+        assert_eq!(keyring.keys(), [public_key(&trusted)]);
+    }
+
+    #[test]
+    fn test_revocation() {
+        let (old, new, leaked) = (test_key(1), test_key(2), test_key(3));
+        let mut keyring = Keyring::new(
+            vec![public_key(&old), public_key(&leaked)],
+            Vec::new(),
+            vec![statement(StatementKind::Endorse, &new, &old)],
+        );
+        assert!(keyring.add_statements(vec![
+            statement(StatementKind::Revoke, &leaked, &new),
+            // Endorsements by a revoked key no longer count
+            statement(StatementKind::Endorse, &test_key(4), &leaked),
+        ]));
+        assert_eq!(keyring.keys(), [public_key(&old), public_key(&new)]);
+        assert!(!keyring.add_statements(vec![statement(StatementKind::Revoke, &leaked, &new)]));
+
+        // An endorsement listed before the revocation of its signer doesn't count either,
+        // nor do the keys that endorsement led to
+        let (endorsed, chained) = (test_key(4), test_key(5));
+        let keyring = Keyring::new(
+            vec![public_key(&old), public_key(&leaked)],
+            Vec::new(),
+            vec![
+                statement(StatementKind::Endorse, &endorsed, &leaked),
+                statement(StatementKind::Endorse, &chained, &endorsed),
+                statement(StatementKind::Endorse, &new, &old),
+                statement(StatementKind::Revoke, &leaked, &new),
+            ],
+        );
+        assert_eq!(keyring.keys(), [public_key(&old), public_key(&new)]);
+
+        // Keys on the embedded revocation list are never trusted
+        let keyring = Keyring::new(
+            vec![public_key(&old)],
+            vec![public_key(&new)],
+            vec![statement(StatementKind::Endorse, &new, &old)],
+        );
+        assert_eq!(keyring.keys(), [public_key(&old)]);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
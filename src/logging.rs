@@ -1,4 +1,7 @@
+use std::io::{self, Write};
+
 use tracing_panic::panic_hook;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{self, layer::SubscriberExt, util::SubscriberInitExt, Layer};
 use windows::core::Result;
 use windows::Win32::Foundation::HANDLE;
@@ -8,6 +11,8 @@ use windows::Win32::System::Console::SetConsoleMode;
 use windows::Win32::System::Console::ENABLE_VIRTUAL_TERMINAL_PROCESSING;
 use windows::Win32::System::Console::STD_OUTPUT_HANDLE;
 
+use crate::token;
+
 pub fn enable_ansi_support() -> Result<()> {
     unsafe {
         let handle = GetStdHandle(STD_OUTPUT_HANDLE)?;
@@ -42,6 +47,7 @@ pub fn den_panic_hook(panic_info: &std::panic::PanicHookInfo) {
     } else {
         message = format!("A panic occurred\nReason: {reason}");
     }
+    let message = token::redact(&message);
 
     let mut message_utf16: Vec<u16> = message.encode_utf16().collect();
     message_utf16.push(0);
@@ -67,7 +73,8 @@ pub fn setup_logging() {
         .with_file(false)
         .with_line_number(false)
         // disable module path
-        .with_target(false);
+        .with_target(false)
+        .with_writer(Redacting(io::stdout));
 
     let filter = tracing_subscriber::filter::EnvFilter::from_default_env().add_directive(
         if cfg!(debug_assertions) {
@@ -80,10 +87,37 @@ pub fn setup_logging() {
     if std::env::var("DEN_DEBUG").is_ok() || cfg!(debug_assertions) {
         let appender = tracing_appender::rolling::never("./", "denlauncher.log");
         let file_log = tracing_subscriber::fmt::layer()
-            .with_writer(appender)
+            .with_writer(Redacting(appender))
             .with_ansi(false);
         registry.with(file_log).init();
     } else {
         registry.init();
     }
 }
+
+/// Log output with secrets redacted, see [`token::redact`].
+struct Redacting<M>(M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+/// Events are formatted in full before they are written, so a secret is never split
+/// across writes.
+struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .write_all(token::redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
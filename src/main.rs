@@ -1,14 +1,35 @@
+mod changelog;
+mod cli;
+mod config;
 mod constants;
+mod download;
+mod extract;
+mod http;
 mod injector;
+mod install;
+mod keyring;
 mod logging;
+mod manifest;
+mod release_cache;
+mod release_source;
+mod save_backup;
 mod save_file_step;
+mod save_format;
+mod save_sync;
 mod steam_id;
+#[cfg(test)]
+mod test_archive;
+#[cfg(test)]
+mod test_server;
+mod token;
+mod update_check;
 mod updater;
 
-use injector::start_game;
+use config::Config;
+use injector::{start_game, wait_for_game_exit};
 use logging::{den_panic_hook, enable_ansi_support, setup_logging};
-use save_file_step::check_saves;
-use updater::start_updater;
+use save_file_step::{check_saves, restore_save, sync_saves_after_game};
+use updater::{install_version, rollback, start_updater, update_from_file, RELAUNCHED_FLAG};
 
 fn main() {
     dotenv::dotenv().ok();
@@ -21,15 +42,79 @@ fn main() {
 
     tracing::info!("Starting DenLauncher v{}", env!("CARGO_PKG_VERSION"));
 
-    if std::env::args().any(|arg| arg == "--skip-update") {
-        tracing::info!("Skipping update check...");
+    let mut config = Config::load();
+    http::configure(&config);
+
+    if let Some(channel) = cli::flag_value("--channel") {
+        match channel.parse() {
+            Ok(channel) => {
+                tracing::info!("Switching update channel to {}", channel);
+                config.channel = Some(channel);
+                config.save();
+            }
+            Err(err) => tracing::error!("{}", err),
+        }
+    }
+
+    let install_tag = cli::flag_value("--install-version");
+
+    if cli::has_flag("--unpin-version") {
+        tracing::info!("Removing version pin, updates will be offered again");
+        config.pinned_version = None;
+        config.save();
+    }
+
+    if cli::has_flag("--pin-version") {
+        let version = install_tag
+            .clone()
+            .unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string());
+        tracing::info!("Pinning version {}, updates will not be offered", version);
+        config.pinned_version = Some(version);
+        config.save();
+    }
+
+    let mut update_check = None;
+    if cli::has_flag(RELAUNCHED_FLAG) {
+        tracing::info!("Relaunched after updating, skipping update check...");
     } else {
-        tracing::info!("Checking for updates...");
-        start_updater();
+        if cli::has_flag("--rollback") {
+            tracing::info!("Rolling back to the previous version...");
+            rollback();
+        }
+
+        if cli::has_flag("--restore-save") {
+            tracing::info!("Restoring a save backup...");
+            let id = cli::flag_value("--restore-save").filter(|id| !id.starts_with("--"));
+            restore_save(&config, id.as_deref());
+        }
+
+        if let Some(path) = cli::flag_value("--update-from") {
+            tracing::info!("Installing update from {}...", path);
+            update_from_file(
+                std::path::Path::new(&path),
+                cli::has_flag("--allow-downgrade"),
+            );
+        }
+
+        if let Some(tag) = install_tag {
+            tracing::info!("Installing version {}...", tag);
+            install_version(&config, &tag);
+        }
+
+        if cli::has_flag("--skip-update") {
+            tracing::info!("Skipping update check...");
+        } else {
+            tracing::info!("Checking for updates...");
+            update_check = start_updater(&mut config);
+        }
     }
 
     tracing::info!("Checking for valid save file...");
-    check_saves();
+    check_saves(&config);
+
+    if let Some(update_check) = update_check {
+        update_check.finish();
+    }
 
     tracing::info!("Starting Elden Ring...");
 
@@ -39,6 +124,14 @@ fn main() {
         std::process::exit(1);
     } else {
         tracing::info!("Elden Ring started successfully!");
-        std::thread::sleep(std::time::Duration::from_secs(5));
+        if cli::has_flag("--no-wait") {
+            std::thread::sleep(std::time::Duration::from_secs(5));
+            return;
+        }
+
+        tracing::info!("Waiting for Elden Ring to exit to sync the save...");
+        wait_for_game_exit();
+        tracing::info!("Elden Ring exited, syncing the save...");
+        sync_saves_after_game(&config);
     }
 }
@@ -0,0 +1,198 @@
+//! Record of the files a release installed, with their size and SHA-256, so files a
+//! later release drops can be told apart from files users added. Release archives carry
+//! the same list in their metadata file.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::download::file_sha256;
+
+/// Metadata file at the root of every release archive. The archive signature covers it,
+/// so it can be trusted once the archive verified.
+pub const RELEASE_METADATA_FILE: &str = "den-release.json";
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    /// Path relative to the install dir, `/`-separated.
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Lists every file under `root`.
+    pub fn build(root: &Path) -> io::Result<Manifest> {
+        let mut files = Vec::new();
+        for entry in WalkDir::new(root).sort_by_file_name() {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(root)
+                .expect("Failed to strip prefix");
+            let path = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let mut file = File::open(entry.path())?;
+            files.push(ManifestEntry {
+                path,
+                size: file.metadata()?.len(),
+                sha256: file_sha256(&mut file)?,
+            });
+        }
+        Ok(Manifest { files })
+    }
+
+    pub fn load(path: &Path) -> Option<Manifest> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents)
+            .map_err(|e| tracing::warn!("Ignoring unreadable manifest {:?}: {}", path, e))
+            .ok()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self).expect("Failed to serialize manifest");
+        std::fs::write(path, contents)
+    }
+
+    /// Files listed here that `newer` no longer ships.
+    pub fn dropped_by<'a>(
+        &'a self,
+        newer: &'a Manifest,
+    ) -> impl Iterator<Item = &'a ManifestEntry> {
+        let kept: std::collections::HashSet<&str> =
+            newer.files.iter().map(|f| f.path.as_str()).collect();
+        self.files
+            .iter()
+            .filter(move |f| !kept.contains(f.path.as_str()))
+    }
+}
+
+impl ManifestEntry {
+    /// The entry's path, or `None` if it would point outside the install dir.
+    pub fn relative_path(&self) -> Option<PathBuf> {
+        let plain_segment = |segment: &str| {
+            let mut components = Path::new(segment).components();
+            matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            )
+        };
+        self.path
+            .split('/')
+            .all(plain_segment)
+            .then(|| self.path.split('/').collect())
+    }
+
+    /// Whether `file` still holds what was installed, i.e. nobody edited it since.
+    pub fn matches(&self, file: &Path) -> bool {
+        let Ok(mut file) = File::open(file) else {
+            return false;
+        };
+        file.metadata().is_ok_and(|m| m.len() == self.size)
+            && file_sha256(&mut file).is_ok_and(|digest| digest == self.sha256)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ReleaseMetadata {
+    pub version: String,
+    pub build_date: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+impl ReleaseMetadata {
+    /// Reads the metadata of an extracted release, `None` if it has none.
+    pub fn load(extracted: &Path) -> Result<Option<ReleaseMetadata>, String> {
+        let path = extracted.join(RELEASE_METADATA_FILE);
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| format!("Invalid release metadata: {e}"))
+    }
+
+    /// Checks that the extracted files are exactly the ones recorded, byte for byte.
+    pub fn check_files(&self, extracted: &Path) -> Result<(), String> {
+        let actual = Manifest::build(extracted)
+            .map_err(|e| format!("Failed to hash extracted files: {e}"))?;
+        let mut actual: HashMap<&str, &ManifestEntry> = actual
+            .files
+            .iter()
+            .filter(|f| f.path != RELEASE_METADATA_FILE)
+            .map(|f| (f.path.as_str(), f))
+            .collect();
+
+        for recorded in &self.files {
+            match actual.remove(recorded.path.as_str()) {
+                Some(file) if file == recorded => {}
+                Some(_) => {
+                    return Err(format!(
+                        "{} does not match its recorded hash",
+                        recorded.path
+                    ))
+                }
+                None => return Err(format!("{} is missing from the archive", recorded.path)),
+            }
+        }
+        match actual.keys().next() {
+            Some(path) => Err(format!("{path} is not listed in the release metadata")),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_dropped_by() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("DENData")).unwrap();
+        std::fs::write(dir.path().join("DENData/denmaps.dll"), "dll").unwrap();
+        std::fs::write(dir.path().join("DENData/old.dll"), "old").unwrap();
+        let old = Manifest::build(dir.path()).unwrap();
+
+        let paths: Vec<_> = old.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["DENData/denmaps.dll", "DENData/old.dll"]);
+        assert_eq!(old.files[0].size, 3);
+        assert!(old.files[0].matches(&dir.path().join("DENData/denmaps.dll")));
+        assert!(!old.files[0].matches(&dir.path().join("DENData/old.dll")));
+
+        std::fs::remove_file(dir.path().join("DENData/old.dll")).unwrap();
+        let new = Manifest::build(dir.path()).unwrap();
+        let dropped: Vec<_> = old.dropped_by(&new).map(|f| f.path.as_str()).collect();
+        assert_eq!(dropped, ["DENData/old.dll"]);
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let entry = |path: &str| ManifestEntry {
+            path: path.to_string(),
+            size: 0,
+            sha256: String::new(),
+        };
+        assert_eq!(
+            entry("DENData/denmaps.dll").relative_path(),
+            Some(Path::new("DENData").join("denmaps.dll"))
+        );
+        assert_eq!(entry("../outside.dll").relative_path(), None);
+        assert_eq!(entry("/etc/passwd").relative_path(), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Release archives shared between machines, e.g. on a network share at events, so an
+//! update is downloaded once instead of on every machine.
+//!
+//! Archives are stored as `<sha256>/<asset name>`. Nothing in the cache is trusted: an
+//! archive is copied to local disk, hashed again and still has to pass the signature
+//! check before it is used.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::download;
+use crate::release_source::ReleaseAsset;
+
+pub struct ReleaseCache {
+    dir: PathBuf,
+}
+
+impl ReleaseCache {
+    pub fn new(dir: PathBuf) -> ReleaseCache {
+        ReleaseCache { dir }
+    }
+
+    fn entry_path(&self, digest: &str, name: &str) -> Option<PathBuf> {
+        let is_sha256 = digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit());
+        let is_file_name = Path::new(name).file_name().is_some_and(|n| n == name);
+        (is_sha256 && is_file_name).then(|| self.dir.join(digest.to_ascii_lowercase()).join(name))
+    }
+
+    /// Copies the cached archive for `asset` to `dest` and returns it rewound, if the
+    /// cache has one with the digest the release advertises. Release assets often share a
+    /// name, so without a digest the cache is skipped.
+    pub fn fetch(&self, asset: &ReleaseAsset, dest: &Path) -> Option<File> {
+        let Some(digest) = asset
+            .digest
+            .as_deref()
+            .and_then(|digest| digest.strip_prefix("sha256:"))
+        else {
+            tracing::debug!(
+                "Not using the release cache for {}, the release publishes no SHA-256 digest",
+                asset.name
+            );
+            return None;
+        };
+        let path = self.entry_path(digest, &asset.name)?;
+        if !path.is_file() {
+            return None;
+        }
+
+        std::fs::copy(&path, dest)
+            .map_err(|e| tracing::warn!("Failed to copy cached archive {:?}: {}", path, e))
+            .ok()?;
+        let mut file = File::open(dest).ok()?;
+        let actual = download::file_sha256(&mut file).ok()?;
+        if !actual.eq_ignore_ascii_case(digest) {
+            tracing::warn!(
+                "Ignoring cached archive {:?}, its SHA-256 is {} instead of {}",
+                path,
+                actual,
+                digest
+            );
+            drop(file);
+            std::fs::remove_file(dest).ok();
+            return None;
+        }
+
+        tracing::info!("Using cached archive {:?}", path);
+        Some(file)
+    }
+
+    /// Adds a verified archive for other machines. A cache that can't be written to
+    /// doesn't fail the update.
+    pub fn store(&self, name: &str, digest: &str, archive: &Path) {
+        let Some(path) = self.entry_path(digest, name) else {
+            return;
+        };
+        if path.is_file() {
+            return;
+        }
+
+        // Copied under a temporary name first, so other machines never see half an archive
+        let partial = path.with_file_name(format!("{}.{}.part", name, std::process::id()));
+        let result = path
+            .parent()
+            .map(std::fs::create_dir_all)
+            .transpose()
+            .and_then(|_| std::fs::copy(archive, &partial))
+            .and_then(|_| std::fs::rename(&partial, &path));
+        match result {
+            Ok(()) => tracing::info!("Added archive to the release cache: {:?}", path),
+            Err(e) => {
+                std::fs::remove_file(&partial).ok();
+                tracing::warn!(
+                    "Failed to add archive to the release cache {:?}: {}",
+                    path,
+                    e
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn asset(name: &str, contents: &[u8]) -> ReleaseAsset {
+        serde_json::from_value(serde_json::json!({
+            "url": "http://localhost/unused",
+            "name": name,
+            "digest": format!("sha256:{:x}", Sha256::digest(contents)),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_store_and_fetch() {
+        let share = tempfile::TempDir::new().unwrap();
+        let local = tempfile::TempDir::new().unwrap();
+        let cache = ReleaseCache::new(share.path().to_path_buf());
+        let asset = asset("DEN-2.1.0.zip", b"archive");
+        let dest = local.path().join("DEN-2.1.0.zip.part");
+        assert!(cache.fetch(&asset, &dest).is_none());
+
+        let downloaded = local.path().join("downloaded.zip");
+        std::fs::write(&downloaded, b"archive").unwrap();
+        let digest = asset
+            .digest
+            .as_deref()
+            .unwrap()
+            .trim_start_matches("sha256:");
+        cache.store(&asset.name, digest, &downloaded);
+        assert!(share.path().join(digest).join("DEN-2.1.0.zip").is_file());
+
+        assert!(cache.fetch(&asset, &dest).is_some());
+        assert_eq!(std::fs::read(&dest).unwrap(), b"archive");
+    }
+
+    #[test]
+    fn test_fetch_rejects_tampered_archive() {
+        let share = tempfile::TempDir::new().unwrap();
+        let local = tempfile::TempDir::new().unwrap();
+        let cache = ReleaseCache::new(share.path().to_path_buf());
+        let asset = asset("DEN-2.1.0.zip", b"archive");
+        let digest = asset
+            .digest
+            .as_deref()
+            .unwrap()
+            .trim_start_matches("sha256:");
+        std::fs::create_dir_all(share.path().join(digest)).unwrap();
+        std::fs::write(share.path().join(digest).join(&asset.name), b"poisoned").unwrap();
+
+        let dest = local.path().join("DEN-2.1.0.zip.part");
+        assert!(cache.fetch(&asset, &dest).is_none());
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn test_entry_path() {
+        let cache = ReleaseCache::new(PathBuf::from("cache"));
+        let digest = "ab".repeat(32);
+        assert!(cache.entry_path(&digest, "DEN-2.1.0.zip").is_some());
+        assert!(cache.entry_path(&digest, "../DEN-2.1.0.zip").is_none());
+        assert!(cache.entry_path("../..", "DEN-2.1.0.zip").is_none());
+    }
+}
@@ -0,0 +1,609 @@
+//! Where the updater finds releases: GitHub, a self-hosted JSON manifest, or a local
+//! (or network share) directory of signed zips.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use const_format::formatcp;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{data_dir, write_atomically},
+    constants::{REPO_NAME, REPO_OWNER},
+    download::{self, RetryPolicy},
+    http, token,
+    updater::Channel,
+};
+
+const USER_AGENT: &str = formatcp!("denlauncher/{}", env!("CARGO_PKG_VERSION"));
+
+/// Upper bound on followed `Link` pages, in case the API keeps pointing onwards.
+const MAX_RELEASE_PAGES: usize = 20;
+
+/// GitHub API responses kept for conditional requests.
+const GITHUB_CACHE_FILE: &str = "github-releases.json";
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ReleaseAsset {
+    pub url: String,
+    pub name: String,
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Checksum published by GitHub or listed in a manifest, e.g. `sha256:<hex>`.
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+/// A release in the shape of GitHub's releases API, which the manifest format reuses.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub draft: bool,
+    /// Release notes, in GitHub-flavoured Markdown.
+    #[serde(default)]
+    pub body: Option<String>,
+    pub assets: Vec<ReleaseAsset>,
+}
+
+impl Release {
+    pub fn version_str(&self) -> &str {
+        self.tag_name.trim_start_matches("v")
+    }
+
+    pub fn version(&self) -> Option<Version> {
+        Version::parse(self.version_str()).ok()
+    }
+
+    /// Drafts are never offered, and releases GitHub marks as pre-releases are kept off stable.
+    pub fn is_on_channel(&self, channel: Channel) -> bool {
+        !self.draft && (!self.prerelease || channel > Channel::Stable)
+    }
+}
+
+pub trait ReleaseSource {
+    /// Short description for logs.
+    fn describe(&self) -> String;
+
+    /// Every release the source offers, in no particular order.
+    fn releases(&self) -> Result<Vec<Release>, String>;
+
+    /// Fetches `asset` into `partial_path`, returning the file rewound to the start and
+    /// the size the source announced, if any.
+    fn download(
+        &self,
+        asset: &ReleaseAsset,
+        partial_path: &Path,
+        retry: RetryPolicy,
+    ) -> Result<(File, Option<u64>), String>;
+}
+
+/// Release source selection in `denlauncher.json`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SourceConfig {
+    Github {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        repo: Option<String>,
+    },
+    /// A JSON array of releases in GitHub's format. Relative asset URLs are resolved
+    /// against the manifest URL.
+    Manifest { url: String },
+    /// A directory of signed zips named after their version, e.g. `DEN-2.0.1.zip`.
+    Directory { path: PathBuf },
+}
+
+pub fn from_config(config: Option<&SourceConfig>) -> Box<dyn ReleaseSource> {
+    match config {
+        None => Box::new(GithubSource::new(REPO_OWNER.clone(), REPO_NAME.clone())),
+        Some(SourceConfig::Github { owner, repo }) => Box::new(GithubSource::new(
+            owner.clone().unwrap_or_else(|| REPO_OWNER.clone()),
+            repo.clone().unwrap_or_else(|| REPO_NAME.clone()),
+        )),
+        Some(SourceConfig::Manifest { url }) => Box::new(ManifestSource { url: url.clone() }),
+        Some(SourceConfig::Directory { path }) => Box::new(DirectorySource { path: path.clone() }),
+    }
+}
+
+pub struct GithubSource {
+    owner: String,
+    repo: String,
+    cache_path: PathBuf,
+}
+
+impl GithubSource {
+    pub fn new(owner: String, repo: String) -> GithubSource {
+        GithubSource {
+            owner,
+            repo,
+            cache_path: data_dir().join(GITHUB_CACHE_FILE),
+        }
+    }
+
+    fn releases_url(&self) -> String {
+        format!(
+            "https://api.github.com/repos/{}/{}/releases?per_page=100",
+            self.owner, self.repo
+        )
+    }
+}
+
+impl ReleaseSource for GithubSource {
+    fn describe(&self) -> String {
+        format!("GitHub {}/{}", self.owner, self.repo)
+    }
+
+    fn releases(&self) -> Result<Vec<Release>, String> {
+        fetch_releases(&self.releases_url(), &self.cache_path)
+    }
+
+    fn download(
+        &self,
+        asset: &ReleaseAsset,
+        partial_path: &Path,
+        retry: RetryPolicy,
+    ) -> Result<(File, Option<u64>), String> {
+        download::download_resumable(
+            || github_request(&asset.url).set("Accept", "application/octet-stream"),
+            partial_path,
+            retry,
+        )
+    }
+}
+
+fn github_request(url: &str) -> ureq::Request {
+    let mut request = http::get(url).set("User-Agent", USER_AGENT);
+    if let Some(token) = token::github_token() {
+        request = request.set("Authorization", &format!("token {token}"));
+    }
+    request
+}
+
+/// A page of the releases API as last served, revalidated with `If-None-Match`. GitHub
+/// doesn't count `304 Not Modified` answers against the rate limit.
+#[derive(Deserialize, Serialize, Clone)]
+struct CachedPage {
+    etag: Option<String>,
+    next: Option<String>,
+    body: String,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+struct ResponseCache {
+    /// Pages by URL.
+    pages: HashMap<String, CachedPage>,
+}
+
+impl ResponseCache {
+    fn load(path: &Path) -> ResponseCache {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) {
+        let contents = serde_json::to_string(self).expect("Failed to serialize cache");
+        write_atomically(path, contents.as_bytes())
+            .map_err(|e| tracing::warn!("Failed to write release cache to {:?}: {}", path, e))
+            .ok();
+    }
+}
+
+/// Fetches every release, following the `rel="next"` links of GitHub's paginated API.
+/// Pages are cached in `cache_path` and only downloaded again when they changed.
+fn fetch_releases(url: &str, cache_path: &Path) -> Result<Vec<Release>, String> {
+    let cached = ResponseCache::load(cache_path);
+    let mut cache = ResponseCache::default();
+    let mut releases = Vec::new();
+    let mut next = Some(url.to_string());
+
+    while let Some(url) = next.take() {
+        if cache.pages.len() == MAX_RELEASE_PAGES {
+            tracing::warn!("Stopped listing releases after {} pages", MAX_RELEASE_PAGES);
+            break;
+        }
+
+        let page = fetch_page(&url, cached.pages.get(&url))?;
+        let parsed: Vec<Release> = serde_json::from_str(&page.body)
+            .map_err(|e| format!("Failed to parse releases: {e}"))?;
+        releases.extend(parsed);
+        next = page.next.clone();
+        cache.pages.insert(url, page);
+    }
+
+    cache.save(cache_path);
+    Ok(releases)
+}
+
+fn fetch_page(url: &str, cached: Option<&CachedPage>) -> Result<CachedPage, String> {
+    let mut request = github_request(url);
+    if let Some(etag) = cached.and_then(|page| page.etag.as_deref()) {
+        request = request.set("If-None-Match", etag);
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, response)) => {
+            return Err(rate_limit_message(&response, SystemTime::now())
+                .unwrap_or_else(|| format!("Failed to fetch releases: HTTP {status}")));
+        }
+        Err(err) => return Err(format!("Failed to fetch releases: {err}")),
+    };
+
+    if let Some(remaining) = response.header("X-RateLimit-Remaining") {
+        tracing::debug!("GitHub API requests left: {}", remaining);
+    }
+    match (response.status(), cached) {
+        (304, Some(cached)) => {
+            tracing::debug!("Releases unchanged since last check: {}", url);
+            Ok(cached.clone())
+        }
+        (304, None) => Err("Failed to fetch releases: unexpected HTTP 304".to_string()),
+        _ => Ok(CachedPage {
+            etag: response.header("ETag").map(str::to_string),
+            next: response.header("Link").and_then(next_page_link),
+            body: response
+                .into_string()
+                .map_err(|e| format!("Failed to read releases: {e}"))?,
+        }),
+    }
+}
+
+/// A readable explanation if `response` is GitHub refusing because of its rate limit.
+fn rate_limit_message(response: &ureq::Response, now: SystemTime) -> Option<String> {
+    let retry_after = response
+        .header("Retry-After")
+        .and_then(|secs| secs.parse::<u64>().ok());
+    if response.header("X-RateLimit-Remaining") != Some("0") && retry_after.is_none() {
+        return None;
+    }
+
+    let wait = retry_after.or_else(|| {
+        let reset = response.header("X-RateLimit-Reset")?.parse::<u64>().ok()?;
+        let now = now.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(reset.saturating_sub(now))
+    });
+    let message = "GitHub API rate limit reached, skipping the update check";
+    Some(match wait {
+        Some(secs) => format!("{message}. It resets in {} minute(s)", secs.div_ceil(60)),
+        None => message.to_string(),
+    })
+}
+
+/// Extracts the `rel="next"` target from a `Link` header.
+fn next_page_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (target, params) = link.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| {
+                target
+                    .trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+pub struct ManifestSource {
+    url: String,
+}
+
+impl ManifestSource {
+    /// Resolves an asset URL relative to the directory the manifest lives in.
+    fn resolve(&self, url: &str) -> String {
+        if url.contains("://") {
+            return url.to_string();
+        }
+        let base = self
+            .url
+            .rsplit_once('/')
+            .map_or(self.url.as_str(), |(base, _)| base);
+        format!("{}/{}", base, url.trim_start_matches('/'))
+    }
+}
+
+impl ReleaseSource for ManifestSource {
+    fn describe(&self) -> String {
+        format!("manifest {}", self.url)
+    }
+
+    fn releases(&self) -> Result<Vec<Release>, String> {
+        let mut releases = http::get(&self.url)
+            .set("User-Agent", USER_AGENT)
+            .call()
+            .map_err(|e| format!("Failed to fetch release manifest: {e}"))?
+            .into_json::<Vec<Release>>()
+            .map_err(|e| format!("Failed to parse release manifest: {e}"))?;
+
+        for asset in releases.iter_mut().flat_map(|r| r.assets.iter_mut()) {
+            asset.url = self.resolve(&asset.url);
+        }
+        Ok(releases)
+    }
+
+    fn download(
+        &self,
+        asset: &ReleaseAsset,
+        partial_path: &Path,
+        retry: RetryPolicy,
+    ) -> Result<(File, Option<u64>), String> {
+        download::download_resumable(
+            || http::get(&asset.url).set("User-Agent", USER_AGENT),
+            partial_path,
+            retry,
+        )
+    }
+}
+
+pub struct DirectorySource {
+    path: PathBuf,
+}
+
+impl ReleaseSource for DirectorySource {
+    fn describe(&self) -> String {
+        format!("directory {:?}", self.path)
+    }
+
+    fn releases(&self) -> Result<Vec<Release>, String> {
+        let entries = std::fs::read_dir(&self.path)
+            .map_err(|e| format!("Failed to read release directory {:?}: {}", self.path, e))?;
+
+        let releases = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "zip"))
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?.to_string();
+                let Some(version) = version_from_file_name(&name) else {
+                    tracing::debug!("Ignoring {:?}, no version in its name", path);
+                    return None;
+                };
+                Some(Release {
+                    tag_name: format!("v{version}"),
+                    prerelease: !version.pre.is_empty(),
+                    draft: false,
+                    body: None,
+                    assets: vec![ReleaseAsset {
+                        url: path.to_string_lossy().into_owned(),
+                        size: path.metadata().ok().map(|m| m.len()),
+                        name,
+                        digest: None,
+                    }],
+                })
+            })
+            .collect();
+        Ok(releases)
+    }
+
+    fn download(
+        &self,
+        asset: &ReleaseAsset,
+        partial_path: &Path,
+        _retry: RetryPolicy,
+    ) -> Result<(File, Option<u64>), String> {
+        // Copy rather than open in place, the archive is cleaned up after installing
+        let size = std::fs::copy(&asset.url, partial_path)
+            .map_err(|e| format!("Failed to copy {}: {}", asset.url, e))?;
+        let file = File::open(partial_path).map_err(|e| e.to_string())?;
+        Ok((file, Some(size)))
+    }
+}
+
+/// Finds the version in a zip name such as `DEN-2.0.0-rc.5.zip` or `den_v2.1.0.zip`:
+/// the first part following a `-`, `_` or space that parses as semver.
+fn version_from_file_name(name: &str) -> Option<Version> {
+    let stem = name.strip_suffix(".zip")?;
+    stem.match_indices(['-', '_', ' '])
+        .map(|(i, _)| &stem[i + 1..])
+        .find_map(|rest| Version::parse(rest.trim_start_matches('v')).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{serve, TestResponse};
+    use std::io::Read;
+    use std::time::Duration;
+
+    const NO_RETRY: RetryPolicy = RetryPolicy {
+        max_attempts: 1,
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+    };
+
+    fn release_json(tags: &[&str]) -> String {
+        let releases: Vec<_> = tags
+            .iter()
+            .map(|tag| format!(r#"{{"tag_name":"{tag}","prerelease":false,"assets":[]}}"#))
+            .collect();
+        format!("[{}]", releases.join(","))
+    }
+
+    #[test]
+    fn test_next_page_link() {
+        let header = r#"<https://api.github.com/repositories/1/releases?page=2>; rel="next", <https://api.github.com/repositories/1/releases?page=5>; rel="last""#;
+        assert_eq!(
+            next_page_link(header).as_deref(),
+            Some("https://api.github.com/repositories/1/releases?page=2")
+        );
+        assert_eq!(
+            next_page_link(r#"<https://example.com/?page=1>; rel="prev""#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fetch_releases_follows_pages() {
+        let server = serve(|request, base_url| match request.path.as_str() {
+            "/releases?page=1" => TestResponse::ok(release_json(&["v1.1.0", "v1.0.0"]))
+                .with_header(
+                    "Link",
+                    &format!(r#"<{base_url}/releases?page=2>; rel="next""#),
+                ),
+            "/releases?page=2" => TestResponse::ok(release_json(&["v1.3.0", "v0.9.0"])),
+            _ => TestResponse::status(404),
+        });
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = dir.path().join(GITHUB_CACHE_FILE);
+        let releases =
+            fetch_releases(&format!("{}/releases?page=1", server.url()), &cache).unwrap();
+        let tags: Vec<_> = releases.iter().map(|r| r.tag_name.as_str()).collect();
+        assert_eq!(tags, ["v1.1.0", "v1.0.0", "v1.3.0", "v0.9.0"]);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_fetch_releases_revalidates_cache() {
+        let server = serve(|request, base_url| {
+            let etag = format!(r#""{}""#, request.path);
+            if request.header("If-None-Match") == Some(etag.as_str()) {
+                return TestResponse::status(304);
+            }
+            match request.path.as_str() {
+                "/releases?page=1" => TestResponse::ok(release_json(&["v1.1.0"]))
+                    .with_header("ETag", &etag)
+                    .with_header(
+                        "Link",
+                        &format!(r#"<{base_url}/releases?page=2>; rel="next""#),
+                    ),
+                "/releases?page=2" => {
+                    TestResponse::ok(release_json(&["v1.0.0"])).with_header("ETag", &etag)
+                }
+                _ => TestResponse::status(404),
+            }
+        });
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = dir.path().join(GITHUB_CACHE_FILE);
+        let url = format!("{}/releases?page=1", server.url());
+
+        let first = fetch_releases(&url, &cache).unwrap();
+        let second = fetch_releases(&url, &cache).unwrap();
+        let tags = |releases: &[Release]| -> Vec<String> {
+            releases.iter().map(|r| r.tag_name.clone()).collect()
+        };
+        assert_eq!(tags(&first), tags(&second));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].header("If-None-Match"), None);
+        assert_eq!(
+            requests[3].header("If-None-Match"),
+            Some(r#""/releases?page=2""#)
+        );
+    }
+
+    #[test]
+    fn test_fetch_releases_rate_limited() {
+        let reset = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 600;
+        let server = serve(move |_, _| {
+            TestResponse::status(403)
+                .with_header("X-RateLimit-Remaining", "0")
+                .with_header("X-RateLimit-Reset", &reset.to_string())
+        });
+        let dir = tempfile::TempDir::new().unwrap();
+        let err = fetch_releases(
+            &format!("{}/releases", server.url()),
+            &dir.path().join(GITHUB_CACHE_FILE),
+        )
+        .unwrap_err();
+        assert!(err.contains("rate limit"), "{err}");
+        assert!(err.contains("10 minute"), "{err}");
+
+        let server = serve(|_, _| TestResponse::status(403));
+        let err = fetch_releases(
+            &format!("{}/releases", server.url()),
+            &dir.path().join(GITHUB_CACHE_FILE),
+        )
+        .unwrap_err();
+        assert!(!err.contains("rate limit"), "{err}");
+    }
+
+    #[test]
+    fn test_manifest_source() {
+        let server = serve(|request, _| match request.path.as_str() {
+            "/den/releases.json" => TestResponse::ok(
+                r#"[{"tag_name":"v2.1.0","assets":[{"name":"DEN.zip","url":"DEN-2.1.0.zip"}]}]"#,
+            ),
+            "/den/DEN-2.1.0.zip" => TestResponse::ok("zip bytes"),
+            _ => TestResponse::status(404),
+        });
+        let source = ManifestSource {
+            url: format!("{}/den/releases.json", server.url()),
+        };
+
+        let releases = source.releases().unwrap();
+        let asset = &releases[0].assets[0];
+        assert_eq!(asset.url, format!("{}/den/DEN-2.1.0.zip", server.url()));
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let (mut file, size) = source
+            .download(asset, &dir.path().join("DEN.zip.part"), NO_RETRY)
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "zip bytes");
+        assert_eq!(size, Some(9));
+    }
+
+    #[test]
+    fn test_directory_source() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("DEN-2.0.1.zip"), "stable").unwrap();
+        std::fs::write(dir.path().join("DEN-2.1.0-rc.1.zip"), "rc").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        std::fs::write(dir.path().join("DEN-latest.zip"), "").unwrap();
+
+        let source = DirectorySource {
+            path: dir.path().to_path_buf(),
+        };
+        let mut releases = source.releases().unwrap();
+        releases.sort_by(|a, b| a.tag_name.cmp(&b.tag_name));
+
+        let tags: Vec<_> = releases.iter().map(|r| r.tag_name.as_str()).collect();
+        assert_eq!(tags, ["v2.0.1", "v2.1.0-rc.1"]);
+        assert!(!releases[0].prerelease);
+        assert!(releases[1].prerelease);
+        assert_eq!(releases[0].assets[0].name, "DEN-2.0.1.zip");
+        assert_eq!(releases[0].assets[0].size, Some(6));
+
+        let partial = dir.path().join("copy.part");
+        let (_, size) = source
+            .download(&releases[0].assets[0], &partial, NO_RETRY)
+            .unwrap();
+        assert_eq!(size, Some(6));
+        assert!(dir.path().join("DEN-2.0.1.zip").exists());
+    }
+
+    #[test]
+    fn test_version_from_file_name() {
+        assert_eq!(
+            version_from_file_name("DEN-2.0.0-rc.5.zip"),
+            Some(Version::parse("2.0.0-rc.5").unwrap())
+        );
+        assert_eq!(
+            version_from_file_name("den_v2.1.0.zip"),
+            Some(Version::parse("2.1.0").unwrap())
+        );
+        assert_eq!(
+            version_from_file_name("DEN-Launcher-2.0.0+patch.2.zip"),
+            Some(Version::parse("2.0.0+patch.2").unwrap())
+        );
+        assert_eq!(version_from_file_name("DEN.zip"), None);
+    }
+}
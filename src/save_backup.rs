@@ -0,0 +1,461 @@
+//! Backups of save files, taken before a sync overwrites them.
+//!
+//! Backups are named `<save stem>-<location>-<UTC time>-<content hash>.<extension>`, e.g.
+//! `ER0000-den-20250201T183000Z-3fa2b1c9d0e1f2a3.dentest`. Saving content that is already
+//! backed up only refreshes the time of the existing backup.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{data_dir, Config};
+use crate::download;
+
+const BACKUP_DIR: &str = "save-backups";
+/// Hex digits of the SHA-256 kept in backup names.
+const HASH_LEN: usize = 16;
+
+pub const DEFAULT_MAX_COUNT: usize = 20;
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The save folders `check_saves` keeps in sync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveLocation {
+    /// The Elden Ring save folder, in the game's own Proton prefix on Linux.
+    EldenRing,
+    /// The save folder in the Proton prefix of the DEN launcher shortcut.
+    Den,
+}
+
+impl SaveLocation {
+    fn tag(self) -> &'static str {
+        match self {
+            SaveLocation::EldenRing => "eldenring",
+            SaveLocation::Den => "den",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<SaveLocation> {
+        [SaveLocation::EldenRing, SaveLocation::Den]
+            .into_iter()
+            .find(|location| location.tag() == tag)
+    }
+}
+
+impl fmt::Display for SaveLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SaveLocation::EldenRing => "Elden Ring prefix",
+            SaveLocation::Den => "DEN prefix",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SaveBackup {
+    pub path: PathBuf,
+    /// Where the backed up save was.
+    pub location: SaveLocation,
+    pub created: SystemTime,
+    /// Start of the SHA-256 of the content, unique enough to identify a backup.
+    pub hash: String,
+}
+
+impl SaveBackup {
+    fn parse(path: PathBuf) -> Option<SaveBackup> {
+        let stem = path.file_stem()?.to_str()?;
+        let mut parts = stem.rsplitn(4, '-');
+        let hash = parts.next()?;
+        let created = parse_timestamp(parts.next()?)?;
+        let location = SaveLocation::from_tag(parts.next()?)?;
+        parts.next().filter(|save_stem| !save_stem.is_empty())?;
+        if hash.len() != HASH_LEN || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(SaveBackup {
+            hash: hash.to_string(),
+            location,
+            created,
+            path,
+        })
+    }
+
+    /// One line for the restore menu: time, size, location and id.
+    pub fn describe(&self) -> String {
+        let size = self.path.metadata().map_or(0, |metadata| metadata.len());
+        format!(
+            "{}  {:>8.1} MB  {:<17}  {}",
+            display_time(self.created),
+            size as f64 / 1_000_000.0,
+            self.location.to_string(),
+            self.hash
+        )
+    }
+}
+
+pub struct SaveBackups {
+    dir: PathBuf,
+    max_count: usize,
+    max_age: Duration,
+}
+
+impl SaveBackups {
+    pub fn new(dir: PathBuf, max_count: usize, max_age: Duration) -> SaveBackups {
+        SaveBackups {
+            dir,
+            max_count,
+            max_age,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> SaveBackups {
+        SaveBackups::new(
+            data_dir().join(BACKUP_DIR),
+            config.save_backup_count.unwrap_or(DEFAULT_MAX_COUNT),
+            config
+                .save_backup_max_age_days
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+                .unwrap_or(DEFAULT_MAX_AGE),
+        )
+    }
+
+    /// Every backup, newest first.
+    pub fn list(&self) -> Vec<SaveBackup> {
+        let mut backups: Vec<SaveBackup> = std::fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| SaveBackup::parse(entry.ok()?.path()))
+            .collect();
+        backups.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| b.path.cmp(&a.path)));
+        backups
+    }
+
+    /// The backup whose hash starts with `id`, which must be unambiguous.
+    pub fn find(&self, id: &str) -> Result<SaveBackup, String> {
+        let id = id.to_ascii_lowercase();
+        let mut matches = self
+            .list()
+            .into_iter()
+            .filter(|backup| !id.is_empty() && backup.hash.starts_with(&id));
+        match (matches.next(), matches.next()) {
+            (Some(backup), None) => Ok(backup),
+            (None, _) => Err(format!("No save backup with id {id}")),
+            (Some(_), Some(_)) => Err(format!("More than one save backup matches id {id}")),
+        }
+    }
+
+    /// Backs up `save`, if it exists, and prunes old backups. Returns the backup.
+    pub fn back_up(&self, save: &Path, location: SaveLocation) -> io::Result<Option<PathBuf>> {
+        self.back_up_at(save, location, SystemTime::now())
+    }
+
+    fn back_up_at(
+        &self,
+        save: &Path,
+        location: SaveLocation,
+        now: SystemTime,
+    ) -> io::Result<Option<PathBuf>> {
+        let mut file = match File::open(save) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let hash = download::file_sha256(&mut file)?[..HASH_LEN].to_string();
+        drop(file);
+
+        let mut name = save.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!(
+            "-{}-{}-{}",
+            location.tag(),
+            format_timestamp(now),
+            hash
+        ));
+        if let Some(extension) = save.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        let backup = self.dir.join(name);
+
+        std::fs::create_dir_all(&self.dir)?;
+        match self
+            .list()
+            .into_iter()
+            .find(|existing| existing.hash == hash)
+        {
+            Some(existing) => {
+                tracing::info!("{:?} is already backed up as {:?}", save, existing.path);
+                std::fs::rename(&existing.path, &backup)?;
+            }
+            None => {
+                std::fs::copy(save, &backup)?;
+                tracing::info!("Backed up {:?} to {:?}", save, backup);
+            }
+        }
+
+        self.prune(now);
+        Ok(Some(backup))
+    }
+
+    /// Keeps the newest `max_count` backups and drops those older than `max_age`. The
+    /// newest backup is kept however old it is.
+    fn prune(&self, now: SystemTime) {
+        for (index, backup) in self.list().into_iter().enumerate() {
+            let age = now.duration_since(backup.created).unwrap_or_default();
+            if index == 0 || (index < self.max_count && age <= self.max_age) {
+                continue;
+            }
+            match std::fs::remove_file(&backup.path) {
+                Ok(()) => tracing::debug!("Removed old save backup {:?}", backup.path),
+                Err(e) => tracing::warn!("Failed to remove {:?}: {}", backup.path, e),
+            }
+        }
+    }
+}
+
+/// Copies `source` over `destination`, backing up what it replaces first. Nothing is
+/// overwritten if the backup fails.
+pub fn replace_save(
+    backups: &SaveBackups,
+    source: &Path,
+    destination: &Path,
+    location: SaveLocation,
+) -> io::Result<u64> {
+    backups.back_up(destination, location).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to back up {destination:?} before replacing it: {e}"),
+        )
+    })?;
+    std::fs::copy(source, destination)
+}
+
+/// Writes `backup` to every save in `destinations`, backing each up first.
+pub fn restore(
+    backups: &SaveBackups,
+    backup: &SaveBackup,
+    destinations: &[(SaveLocation, PathBuf)],
+) -> io::Result<()> {
+    // Backing up the current saves may rename or prune the chosen backup, so restore
+    // from a copy
+    let restored = tempfile::NamedTempFile::new()?;
+    std::fs::copy(&backup.path, restored.path())?;
+
+    for (location, destination) in destinations {
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        replace_save(backups, restored.path(), destination, *location)?;
+        tracing::info!("Restored {} to {:?}", backup.hash, destination);
+    }
+    Ok(())
+}
+
+/// UTC time as `YYYY-MM-DD HH:MM:SS UTC`, for people.
+pub fn display_time(time: SystemTime) -> String {
+    let timestamp = format_timestamp(time);
+    format!(
+        "{}-{}-{} {}:{}:{} UTC",
+        &timestamp[..4],
+        &timestamp[4..6],
+        &timestamp[6..8],
+        &timestamp[9..11],
+        &timestamp[11..13],
+        &timestamp[13..15]
+    )
+}
+
+/// UTC time as `YYYYMMDDTHHMMSSZ`.
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs = secs % 86_400;
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let digits = timestamp.strip_suffix('Z')?;
+    let (date, time) = digits.split_once('T')?;
+    if date.len() != 8
+        || time.len() != 6
+        || !date.bytes().chain(time.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let field = |s: &str| s.parse::<u32>().ok();
+    let (year, month, day) = (field(&date[..4])?, field(&date[4..6])?, field(&date[6..])?);
+    let (hours, minutes, seconds) = (field(&time[..2])?, field(&time[2..4])?, field(&time[4..])?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hours > 23
+        || minutes > 59
+        || seconds > 59
+    {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year as i64, month, day)).ok()?;
+    let secs = days * 86_400 + u64::from(hours * 3600 + minutes * 60 + seconds);
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+// Conversions between days since the epoch and calendar dates, after Howard Hinnant's
+// `civil_from_days` and `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "19700101T000000Z");
+        assert_eq!(format_timestamp(at(1_738_434_600)), "20250201T183000Z");
+        assert_eq!(format_timestamp(at(951_825_600)), "20000229T120000Z");
+        for secs in [0, 951_825_600, 1_738_434_600, 4_102_444_799] {
+            assert_eq!(parse_timestamp(&format_timestamp(at(secs))), Some(at(secs)));
+        }
+        assert_eq!(parse_timestamp("20251301T000000Z"), None);
+        assert_eq!(parse_timestamp("2025-02-01"), None);
+    }
+
+    #[test]
+    fn test_back_up_deduplicates() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let backups = SaveBackups::new(dir.path().join("backups"), 10, DEFAULT_MAX_AGE);
+        let save = dir.path().join("ER0000.dentest");
+        assert!(backups.back_up(&save, SaveLocation::Den).unwrap().is_none());
+
+        std::fs::write(&save, b"level 1").unwrap();
+        let first = backups
+            .back_up_at(&save, SaveLocation::Den, at(1_738_434_600))
+            .unwrap()
+            .unwrap();
+        let name = first.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("ER0000-den-20250201T183000Z-"));
+        assert!(name.ends_with(".dentest"));
+
+        // The same content again only moves the backup to the new time
+        let again = backups
+            .back_up_at(&save, SaveLocation::Den, at(1_738_434_660))
+            .unwrap()
+            .unwrap();
+        assert!(!first.exists());
+        assert_eq!(backups.list().len(), 1);
+        assert_eq!(backups.list()[0].path, again);
+
+        std::fs::write(&save, b"level 2").unwrap();
+        backups
+            .back_up_at(&save, SaveLocation::Den, at(1_738_434_720))
+            .unwrap();
+        let list = backups.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(std::fs::read(&list[0].path).unwrap(), b"level 2");
+        assert_eq!(std::fs::read(&list[1].path).unwrap(), b"level 1");
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let backups = SaveBackups::new(dir.path().join("backups"), 3, 10 * DAY);
+        let save = dir.path().join("ER0000.dentest");
+        let start = at(1_738_434_600);
+        for i in 0..5u32 {
+            std::fs::write(&save, i.to_string()).unwrap();
+            backups
+                .back_up_at(&save, SaveLocation::Den, start + DAY * i)
+                .unwrap();
+        }
+        let contents = |list: Vec<SaveBackup>| -> Vec<String> {
+            list.iter()
+                .map(|b| std::fs::read_to_string(&b.path).unwrap())
+                .collect()
+        };
+        assert_eq!(contents(backups.list()), ["4", "3", "2"]);
+
+        // Long after, only the newest backup survives
+        std::fs::write(&save, "5").unwrap();
+        backups
+            .back_up_at(&save, SaveLocation::Den, start + DAY * 40)
+            .unwrap();
+        backups.prune(start + DAY * 100);
+        assert_eq!(contents(backups.list()), ["5"]);
+    }
+
+    #[test]
+    fn test_replace_save() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let backups = SaveBackups::new(dir.path().join("backups"), 10, DEFAULT_MAX_AGE);
+        let (source, destination) = (dir.path().join("new"), dir.path().join("ER0000.dentest"));
+        std::fs::write(&source, b"new").unwrap();
+        std::fs::write(&destination, b"old").unwrap();
+
+        replace_save(&backups, &source, &destination, SaveLocation::EldenRing).unwrap();
+        assert_eq!(std::fs::read(&destination).unwrap(), b"new");
+        let backup = &backups.list()[0];
+        assert_eq!(std::fs::read(&backup.path).unwrap(), b"old");
+        assert_eq!(backup.location, SaveLocation::EldenRing);
+    }
+
+    #[test]
+    fn test_find_and_restore() {
+        let dir = tempfile::TempDir::new().unwrap();
+        // Room for a single backup, so backing up the current save prunes the chosen one
+        let backups = SaveBackups::new(dir.path().join("backups"), 1, DEFAULT_MAX_AGE);
+        let elden_ring = dir.path().join("EldenRing").join("ER0000.dentest");
+        let den = dir.path().join("Den").join("ER0000.dentest");
+        std::fs::create_dir_all(elden_ring.parent().unwrap()).unwrap();
+        std::fs::write(&elden_ring, b"good").unwrap();
+        backups
+            .back_up(&elden_ring, SaveLocation::EldenRing)
+            .unwrap();
+        std::fs::write(&elden_ring, b"broken").unwrap();
+
+        let backup = backups.list().remove(0);
+        assert_eq!(backups.find(&backup.hash[..6]).unwrap().path, backup.path);
+        assert!(backups.find("").is_err());
+        assert!(backups.find("zz").is_err());
+
+        let destinations = [
+            (SaveLocation::EldenRing, elden_ring.clone()),
+            (SaveLocation::Den, den.clone()),
+        ];
+        restore(&backups, &backup, &destinations).unwrap();
+        assert_eq!(std::fs::read(&elden_ring).unwrap(), b"good");
+        assert_eq!(std::fs::read(&den).unwrap(), b"good");
+        // The save that was replaced is backed up
+        assert_eq!(std::fs::read(&backups.list()[0].path).unwrap(), b"broken");
+    }
+}
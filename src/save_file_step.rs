@@ -2,6 +2,10 @@ use crate::constants::{
     DEN_SAVE, OLD_SAVE_TIME_MARK, SAVE_STEM, VALID_SOURCE_SAVE_FILE_EXTENSIONS, ELDENRING_ID,
 };
 use crate::{constants::SAVE_EXTENSION, steam_id};
+use crate::config::Config;
+use crate::save_backup::{self, replace_save, SaveBackups, SaveLocation};
+use crate::save_format::SaveFile;
+use crate::save_sync::{self, SavePair};
 use cli_select::Select;
 use std::io::stdout;
 use std::thread;
@@ -18,12 +22,13 @@ use std::{
 
 const STEAM_ID_IDENT: u64 = 0x0110_0001_0000_0000;
 
+fn running_under_linux() -> bool {
+    std::env::var("WINEPREFIX").is_ok() || std::env::var("PROTON_NO_ESYNC").is_ok()
+}
+
 fn get_save_list(steam_id: u64) -> Option<Vec<PathBuf>> {
     let save_file_path: PathBuf;
-    let running_under_linux = std::env::var("WINEPREFIX").is_ok()
-                    || std::env::var("PROTON_NO_ESYNC").is_ok();
-
-    if running_under_linux{
+    if running_under_linux() {
         //build save_file_path
         let steam_dir = env::var("STEAM_COMPAT_CLIENT_INSTALL_PATH").unwrap();
         let appdata = format!("{}/steamapps/compatdata/{}/pfx/drive_c/users/steamuser/AppData/Roaming/", steam_dir, ELDENRING_ID);
@@ -105,25 +110,6 @@ fn get_den_save_location(steam_id: u64) -> String {
     save_file_path
 }
 
-fn get_save_list_linux_den(steam_id: u64) -> Option<Vec<PathBuf>> {
-    let save_file_path = get_den_save_location(steam_id);
-
-    //Return vector of all saves in den save file location
-    tracing::info!("Save file path linux den: {:?}", save_file_path);
-    let mut save_files = Vec::new();
-    let save_stem = OsStr::new(SAVE_STEM);
-    for entry in std::fs::read_dir(&save_file_path).ok()? {
-        let path = entry.unwrap().path();
-        if path.is_dir() {
-            continue;
-        }
-        if path.file_stem() == Some(save_stem) {
-            save_files.push(path);
-        }
-    }
-    Some(save_files)
-}
-
 fn pick_base_save(saves: Vec<PathBuf>) -> Option<PathBuf> {
     tracing::warn!("No {} save file found", &*DEN_SAVE);
     println!("Select a save file to use as a base:");
@@ -131,19 +117,63 @@ fn pick_base_save(saves: Vec<PathBuf>) -> Option<PathBuf> {
 
     let save_files_map = saves
         .iter()
-        .map(|f| (f.file_name().unwrap().to_str().unwrap(), f))
+        .map(|f| (describe_save(f), f))
         .collect::<HashMap<_, _>>();
 
-    let mut keys: Vec<_> = save_files_map.keys().collect();
+    let mut keys: Vec<&str> = save_files_map.keys().map(String::as_str).collect();
     // Insert create new option
-    keys.push(&"Create new save");
+    keys.push("Create new save");
     keys.reverse();
 
     let &selected_save = Select::new(&keys, stdout()).start();
     Some(save_files_map.get(selected_save)?.to_path_buf())
 }
 
-pub fn check_saves() {
+/// The file name followed by the characters in the save, e.g.
+/// `ER0000.sl2: Tarnished (level 150, 42h 07m)`.
+fn describe_save(path: &Path) -> String {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    match SaveFile::read(path) {
+        Ok(save) => {
+            log_save_layout(path, &save);
+            let characters: Vec<String> = save.active_slots().map(|slot| slot.describe()).collect();
+            if characters.is_empty() {
+                format!("{}: no characters", file_name)
+            } else {
+                format!("{}: {}", file_name, characters.join(", "))
+            }
+        }
+        Err(e) => {
+            tracing::warn!("{}", e);
+            file_name.into_owned()
+        }
+    }
+}
+
+/// Logs the container layout, to tell a save the picker misreads from a broken one.
+fn log_save_layout(path: &Path, save: &SaveFile) {
+    let entries: Vec<String> = save
+        .entries()
+        .iter()
+        .map(|entry| format!("{} ({} bytes)", entry.name, entry.size()))
+        .collect();
+    tracing::debug!(
+        "{:?}: BND4 version {}, {} entries: {}",
+        path,
+        save.header().version,
+        save.header().entry_count,
+        entries.join(", ")
+    );
+    for slot in save.active_slots() {
+        match save.slot_entry(slot) {
+            Some(entry) => tracing::debug!("Slot {} ({}) is in {}", slot.index, slot.name, entry.name),
+            None => tracing::warn!("Slot {} ({}) is in use but has no entry", slot.index, slot.name),
+        }
+    }
+}
+
+pub fn check_saves(config: &Config) {
+    let backups = SaveBackups::from_config(config);
     let steam_id = steam_id::get_steam_id();
     let saves: Vec<PathBuf> = get_save_list(steam_id)
         .map(|s| {
@@ -160,56 +190,19 @@ pub fn check_saves() {
         })
         .unwrap_or_default();
 
-    let running_under_linux = std::env::var("WINEPREFIX").is_ok()
-                    || std::env::var("PROTON_NO_ESYNC").is_ok();
-
-    if running_under_linux{
-        let steam_dir = env::var("STEAM_COMPAT_CLIENT_INSTALL_PATH").unwrap();
-        let appdata = format!("{}steamapps/compatdata/{}/pfx/drive_c/users/steamuser/AppData/Roaming/", steam_dir, ELDENRING_ID);
-        let elden_ring_save_path = format!("{}{}{}", appdata,"EldenRing/",steam_id );
-        let den_path = get_den_save_location(steam_id);
+    if running_under_linux() {
+        // Same paths as the sync after the game exits, so both compare the same files
+        let pair = save_pair(&den_save_paths(steam_id));
         // Create the den save directory if it doesn't exist
-        std::fs::create_dir_all(&den_path)
+        std::fs::create_dir_all(pair.den.parent().unwrap())
             .expect("Failed to create den save directory");
 
-        //first get save files in the linux den location
-        let saves_linux: Vec<PathBuf> = get_save_list_linux_den(steam_id)
-            .map(|s| {
-                s.into_iter()
-                    .inspect(|f| tracing::info!("Checking save file: {:?}", f))
-                    .filter(|f| {
-                        f.metadata().unwrap().modified().unwrap() >= UNIX_EPOCH + OLD_SAVE_TIME_MARK
-                    })
-                    .filter(|f| {
-                        let ext = f.extension().unwrap().to_str().unwrap();
-                        VALID_SOURCE_SAVE_FILE_EXTENSIONS.contains(&ext) || ext == *SAVE_EXTENSION
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
-
-        //check for existing valid den save file and sync it if it exists. 
-        for save in &saves_linux {
-            let save_name = save.file_name().unwrap().to_str().unwrap();
-            if save_name.eq(&format!("{}.{}", SAVE_STEM, &*SAVE_EXTENSION)) {
-                tracing::info!("Found valid save file: {:?}", save);
-                //Copy and overwrite the ER000.dentest save file in the Elden Ring save folder aka sync
-                let destination_file = format!("{}/ER0000.dentest", elden_ring_save_path);
-                match std::fs::copy(save, &destination_file) {
-                    Ok(_) => {
-                        tracing::info!("Successfully copied save file to: {}", destination_file);
-                    },
-                    Err(e) => {
-                        tracing::error!("Failed to sync .dentest save file with one in Elden Ring folder: {}", e);
-                    }
-                }       
-                return;
-            }
+        //sync the den save between both prefixes if either has one
+        if pair.den.is_file() || pair.elden_ring.is_file() {
+            save_sync::sync(&backups, &pair);
+            return;
         }
 
-        //No valid save in linux den found, so continue
-        //check for save files in elden ring save folder
-
         //If none in Elden Ring folder 
         if saves.is_empty() {
             tracing::warn!(
@@ -219,35 +212,13 @@ pub fn check_saves() {
             return;
         }
 
-        //Since there are some, check for a .dentest file
-        //if one is found, copy it to den save location
-        for save in &saves {
-            let save_name = save.file_name().unwrap().to_str().unwrap();
-            if save_name.eq(&format!("{}.{}", SAVE_STEM, &*SAVE_EXTENSION)) {
-                tracing::info!("Found valid save file in Elden Ring save location: {:?}", save);
-                let destination_file = format!("{}/ER0000.dentest", den_path);
-                match std::fs::copy(save, &destination_file) {
-                    Ok(_) => {
-                        tracing::info!("Successfully copied save file to: {}", destination_file);
-                    },
-                    Err(e) => {
-                        tracing::error!("Failed to copy .dentest save file from Elden Ring save folder to DEN save folder: {}", e);
-                    }
-                }
-                return;
-            }
-        }
-        
         //since no .dentest file was found in either elden ring or den folder, let user pick a base save
         let save = pick_base_save(saves);
         if let Some(s) = save {
             tracing::debug!("Selected save: {:?}", s);
 
 
-            let destination = PathBuf::from(&den_path)
-                .join(SAVE_STEM)
-                .with_extension(&*SAVE_EXTENSION);
-            std::fs::copy(&s, &destination)
+            replace_save(&backups, &s, &pair.den, SaveLocation::Den)
                 .expect("Failed to copy save file");
         }
 
@@ -278,15 +249,121 @@ pub fn check_saves() {
         let save = pick_base_save(saves);
         if let Some(s) = save {
             tracing::debug!("Selected save: {:?}", s);
-            std::fs::copy(
+            replace_save(
+                &backups,
                 &s,
-                s.parent()
+                &s.parent()
                     .unwrap()
                     .join(SAVE_STEM)
                     .with_extension(&*SAVE_EXTENSION),
+                SaveLocation::EldenRing,
             )
             .expect("Failed to copy save file");
         }
     }
 }
     
+
+/// The DEN save in every folder `check_saves` manages.
+fn den_save_paths(steam_id: u64) -> Vec<(SaveLocation, PathBuf)> {
+    if running_under_linux() {
+        let steam_dir = env::var("STEAM_COMPAT_CLIENT_INSTALL_PATH").unwrap();
+        let elden_ring_save_path = PathBuf::from(format!(
+            "{}/steamapps/compatdata/{}/pfx/drive_c/users/steamuser/AppData/Roaming/EldenRing/{}",
+            steam_dir, ELDENRING_ID, steam_id
+        ));
+        vec![
+            (SaveLocation::EldenRing, elden_ring_save_path.join(&*DEN_SAVE)),
+            (
+                SaveLocation::Den,
+                PathBuf::from(get_den_save_location(steam_id)).join(&*DEN_SAVE),
+            ),
+        ]
+    } else {
+        let appdata = std::env::var("APPDATA").expect("APPDATA not found");
+        let save_file_path = Path::new(&appdata)
+            .join("EldenRing")
+            .join(steam_id.to_string());
+        vec![(SaveLocation::EldenRing, save_file_path.join(&*DEN_SAVE))]
+    }
+}
+
+/// The DEN save in both prefixes, from the paths `den_save_paths` returns on Linux.
+fn save_pair(paths: &[(SaveLocation, PathBuf)]) -> SavePair {
+    let path = |location| {
+        paths
+            .iter()
+            .find(|(l, _)| *l == location)
+            .map(|(_, path)| path.clone())
+            .expect("Missing save location")
+    };
+    SavePair {
+        den: path(SaveLocation::Den),
+        elden_ring: path(SaveLocation::EldenRing),
+    }
+}
+
+/// Syncs the save the game just wrote back to the other prefix and backs it up.
+pub fn sync_saves_after_game(config: &Config) {
+    let backups = SaveBackups::from_config(config);
+    let paths = den_save_paths(steam_id::get_steam_id());
+    if running_under_linux() {
+        save_sync::sync(&backups, &save_pair(&paths));
+    }
+
+    // Both prefixes hold the same save after syncing, so one backup covers them
+    if let Some((location, path)) = paths.iter().find(|(_, path)| path.is_file()) {
+        match backups.back_up(path, *location) {
+            Ok(_) => tracing::info!("Backed up the save in the {}", location),
+            Err(e) => tracing::error!("Failed to back up {:?}: {}", path, e),
+        }
+    }
+}
+
+/// Restores a save backup to every save location, backing up the saves it replaces.
+/// Without an `id`, lets the user pick the backup.
+pub fn restore_save(config: &Config, id: Option<&str>) {
+    let backups = SaveBackups::from_config(config);
+    let backup = match id {
+        Some(id) => backups.find(id).map_err(|err| tracing::error!("{}", err)).ok(),
+        None => pick_backup(&backups),
+    };
+    let Some(backup) = backup else {
+        thread::sleep(Duration::from_secs(5));
+        std::process::exit(1);
+    };
+
+    let destinations = den_save_paths(steam_id::get_steam_id());
+    match save_backup::restore(&backups, &backup, &destinations) {
+        Ok(()) => tracing::info!(
+            "Restored the save backup from {}",
+            save_backup::display_time(backup.created)
+        ),
+        Err(e) => tracing::error!("Failed to restore save backup: {}", e),
+    }
+    thread::sleep(Duration::from_secs(5));
+    std::process::exit(0);
+}
+
+fn pick_backup(backups: &SaveBackups) -> Option<save_backup::SaveBackup> {
+    let list = backups.list();
+    if list.is_empty() {
+        tracing::error!("There are no save backups to restore");
+        return None;
+    }
+
+    println!("Select a save backup to restore:");
+    println!("Use the arrow keys to navigate and Enter to select.\n");
+    let mut labels: Vec<String> = list.iter().map(|backup| backup.describe()).collect();
+    labels.push("Cancel".to_string());
+
+    let selected = Select::new(&labels, stdout()).start();
+    let backup = labels
+        .iter()
+        .position(|label| label == selected)
+        .and_then(|index| list.get(index).cloned());
+    if backup.is_none() {
+        tracing::info!("No save restored");
+    }
+    backup
+}
@@ -0,0 +1,375 @@
+//! Reads Elden Ring save files (`.sl2`, `.co2`, `.dentest`).
+//!
+//! A save is a BND4 container with 10 character slots (`USERDATA_00` to `USERDATA_09`),
+//! `USERDATA_10`, which holds the account data: which slots are in use and a summary of
+//! each character, and on PC `USERDATA_11` with regulation data. Every entry starts with
+//! an MD5 checksum of the rest of it.
+
+use std::path::Path;
+use std::time::Duration;
+
+pub const SLOT_COUNT: usize = 10;
+
+const MAGIC: &[u8] = b"BND4";
+const HEADER_SIZE: usize = 0x40;
+const ENTRY_HEADER_SIZE: usize = 0x20;
+const CHECKSUM_SIZE: usize = 0x10;
+const USER_DATA_ENTRY: &str = "USERDATA_10";
+
+// Offsets within USERDATA_10, after the checksum
+const ACTIVE_SLOTS_OFFSET: usize = 0x1954;
+const SUMMARIES_OFFSET: usize = 0x195E;
+const SUMMARY_SIZE: usize = 0x24C;
+// Offsets within a character summary
+const NAME_LENGTH: usize = 16;
+const LEVEL_OFFSET: usize = 0x22;
+const PLAY_TIME_OFFSET: usize = 0x26;
+
+/// A character slot as summarized in `USERDATA_10`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slot {
+    /// Position in the game's list, also the number of its `USERDATA_0x` entry.
+    pub index: usize,
+    pub active: bool,
+    pub name: String,
+    pub level: u32,
+    pub play_time: Duration,
+}
+
+impl Slot {
+    pub fn describe(&self) -> String {
+        let minutes = self.play_time.as_secs() / 60;
+        format!(
+            "{} (level {}, {}h {:02}m)",
+            self.name,
+            self.level,
+            minutes / 60,
+            minutes % 60
+        )
+    }
+}
+
+/// The BND4 container header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: String,
+    pub entry_count: usize,
+}
+
+/// An entry of the container's entry table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    data: std::ops::Range<usize>,
+}
+
+impl Entry {
+    /// Size in bytes, including the checksum.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+pub struct SaveFile {
+    header: Header,
+    entries: Vec<Entry>,
+    slots: Vec<Slot>,
+}
+
+impl SaveFile {
+    pub fn read(path: &Path) -> Result<SaveFile, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        SaveFile::parse(&data).map_err(|e| format!("{:?} is not a valid save: {}", path, e))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<SaveFile, String> {
+        let (header, entries) = parse_entries(data)?;
+        if entries.len() <= SLOT_COUNT {
+            return Err(format!(
+                "expected more than {} entries, found {}",
+                SLOT_COUNT,
+                entries.len()
+            ));
+        }
+        let user_data = entries
+            .iter()
+            .find(|entry| entry.name == USER_DATA_ENTRY)
+            .ok_or_else(|| format!("no {} entry", USER_DATA_ENTRY))?;
+        let user_data = payload(&data[user_data.data.clone()])?;
+        let slots = parse_slots(user_data)?;
+        Ok(SaveFile {
+            header,
+            entries,
+            slots,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The entry holding the full data of `slot`.
+    pub fn slot_entry(&self, slot: &Slot) -> Option<&Entry> {
+        let name = format!("USERDATA_{:02}", slot.index);
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// The slots that hold a character.
+    pub fn active_slots(&self) -> impl Iterator<Item = &Slot> {
+        self.slots.iter().filter(|slot| slot.active)
+    }
+}
+
+fn parse_entries(data: &[u8]) -> Result<(Header, Vec<Entry>), String> {
+    if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+        return Err("not a BND4 container".to_string());
+    }
+    if data[0x08] != 0 {
+        return Err("big-endian containers are not supported".to_string());
+    }
+    let count = read_u32(data, 0x0C)? as usize;
+    let version = String::from_utf8_lossy(&read_bytes::<8>(data, 0x18)?)
+        .trim_end_matches('\0')
+        .to_string();
+    let entry_header_size = read_u64(data, 0x20)? as usize;
+    if entry_header_size < ENTRY_HEADER_SIZE {
+        return Err(format!(
+            "entry headers are only {} bytes",
+            entry_header_size
+        ));
+    }
+
+    let entries = (0..count)
+        .map(|i| {
+            let field = |delta: usize| {
+                i.checked_mul(entry_header_size)
+                    .and_then(|offset| offset.checked_add(HEADER_SIZE + delta))
+                    .ok_or_else(|| format!("entry header {} is out of bounds", i))
+            };
+            let size = read_u64(data, field(0x08)?)? as usize;
+            let offset = read_u32(data, field(0x10)?)? as usize;
+            let name = read_name(data, read_u32(data, field(0x14)?)? as usize)?;
+            let end = offset
+                .checked_add(size)
+                .filter(|&end| end <= data.len() && size >= CHECKSUM_SIZE)
+                .ok_or_else(|| format!("entry {} is out of bounds", name))?;
+            Ok(Entry {
+                name,
+                data: offset..end,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok((
+        Header {
+            version,
+            entry_count: count,
+        },
+        entries,
+    ))
+}
+
+fn payload(entry: &[u8]) -> Result<&[u8], String> {
+    entry
+        .get(CHECKSUM_SIZE..)
+        .ok_or_else(|| "entry is too short".to_string())
+}
+
+fn parse_slots(user_data: &[u8]) -> Result<Vec<Slot>, String> {
+    let active = user_data
+        .get(ACTIVE_SLOTS_OFFSET..ACTIVE_SLOTS_OFFSET + SLOT_COUNT)
+        .ok_or_else(|| "USERDATA_10 is too short".to_string())?;
+    (0..SLOT_COUNT)
+        .map(|index| {
+            let summary = SUMMARIES_OFFSET + index * SUMMARY_SIZE;
+            let name: Vec<u16> = (0..NAME_LENGTH)
+                .map(|i| read_u16(user_data, summary + i * 2))
+                .collect::<Result<_, _>>()?;
+            let name = name.split(|&c| c == 0).next().unwrap_or_default();
+            Ok(Slot {
+                index,
+                active: active[index] != 0,
+                name: String::from_utf16_lossy(name),
+                level: read_u32(user_data, summary + LEVEL_OFFSET)?,
+                play_time: Duration::from_secs(
+                    read_u32(user_data, summary + PLAY_TIME_OFFSET)?.into(),
+                ),
+            })
+        })
+        .collect()
+}
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], String> {
+    offset
+        .checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("data at {:#x} is out of bounds", offset))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    read_bytes(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    read_bytes(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, String> {
+    read_bytes(data, offset).map(u64::from_le_bytes)
+}
+
+/// Reads a null-terminated UTF-16 name.
+fn read_name(data: &[u8], offset: usize) -> Result<String, String> {
+    let mut name = Vec::new();
+    loop {
+        let at = offset
+            .checked_add(name.len() * 2)
+            .ok_or_else(|| "entry name is out of bounds".to_string())?;
+        match read_u16(data, at)? {
+            0 => return Ok(String::from_utf16_lossy(&name)),
+            c => name.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOT_SIZE: usize = 0x100;
+    const USER_DATA_SIZE: usize = SUMMARIES_OFFSET + SLOT_COUNT * SUMMARY_SIZE;
+
+    /// A save with the given characters in the first slots, laid out like a PC save with
+    /// `USERDATA_11` last. Slot contents, regulation data and checksums are filler, the
+    /// parser doesn't look at them.
+    fn fixture(characters: &[(&str, u32, u32)]) -> Vec<u8> {
+        let mut user_data = vec![0; USER_DATA_SIZE];
+        for (index, &(name, level, seconds)) in characters.iter().enumerate() {
+            user_data[ACTIVE_SLOTS_OFFSET + index] = 1;
+            let summary = SUMMARIES_OFFSET + index * SUMMARY_SIZE;
+            for (i, c) in name.encode_utf16().enumerate() {
+                user_data[summary + i * 2..summary + i * 2 + 2].copy_from_slice(&c.to_le_bytes());
+            }
+            user_data[summary + LEVEL_OFFSET..summary + LEVEL_OFFSET + 4]
+                .copy_from_slice(&level.to_le_bytes());
+            user_data[summary + PLAY_TIME_OFFSET..summary + PLAY_TIME_OFFSET + 4]
+                .copy_from_slice(&seconds.to_le_bytes());
+        }
+        let mut entries: Vec<Vec<u8>> = (0..SLOT_COUNT).map(|i| vec![i as u8; SLOT_SIZE]).collect();
+        entries.push(user_data);
+        entries.push(vec![0x11; SLOT_SIZE]);
+
+        let count = entries.len();
+        let names_offset = HEADER_SIZE + count * ENTRY_HEADER_SIZE;
+        let name = |i: usize| -> Vec<u8> {
+            format!("USERDATA_{:02}\0", i)
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect()
+        };
+        let names: Vec<u8> = (0..count).flat_map(name).collect();
+        let name_size = names.len() / count;
+
+        let mut data = vec![0; names_offset];
+        data[..4].copy_from_slice(MAGIC);
+        data[0x0C..0x10].copy_from_slice(&(count as u32).to_le_bytes());
+        data[0x10..0x18].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+        data[0x18..0x20].copy_from_slice(b"00000001");
+        data[0x20..0x28].copy_from_slice(&(ENTRY_HEADER_SIZE as u64).to_le_bytes());
+        data.extend(names);
+
+        for (i, entry) in entries.iter().enumerate() {
+            let header = HEADER_SIZE + i * ENTRY_HEADER_SIZE;
+            let size = CHECKSUM_SIZE + entry.len();
+            let offset = data.len();
+            data[header] = 0x50;
+            data[header + 0x04..header + 0x08].copy_from_slice(&(-1i32).to_le_bytes());
+            data[header + 0x08..header + 0x10].copy_from_slice(&(size as u64).to_le_bytes());
+            data[header + 0x10..header + 0x14].copy_from_slice(&(offset as u32).to_le_bytes());
+            data[header + 0x14..header + 0x18]
+                .copy_from_slice(&((names_offset + i * name_size) as u32).to_le_bytes());
+            data.extend([0xAA; CHECKSUM_SIZE]);
+            data.extend(entry);
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let save = SaveFile::parse(&fixture(&[
+            ("Tarnished", 150, 3600 * 42 + 60 * 7),
+            ("Melina", 1, 59),
+        ]))
+        .unwrap();
+        let slots: Vec<_> = save.active_slots().collect();
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[0].name, "Tarnished");
+        assert_eq!(slots[0].level, 150);
+        assert_eq!(slots[0].describe(), "Tarnished (level 150, 42h 07m)");
+        assert_eq!(slots[1].play_time, Duration::from_secs(59));
+
+        assert_eq!(save.header().version, "00000001");
+        assert_eq!(save.header().entry_count, 12);
+        let names: Vec<_> = save
+            .entries()
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names[0], "USERDATA_00");
+        assert_eq!(names[11], "USERDATA_11");
+        let entry = save.slot_entry(slots[1]).unwrap();
+        assert_eq!(entry.name, "USERDATA_01");
+        assert_eq!(entry.size(), CHECKSUM_SIZE + SLOT_SIZE);
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("ER0000.sl2");
+        std::fs::write(&path, fixture(&[])).unwrap();
+        assert_eq!(SaveFile::read(&path).unwrap().active_slots().count(), 0);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_saves() {
+        assert!(SaveFile::parse(b"BND3").is_err());
+        assert!(SaveFile::parse(&[0; HEADER_SIZE]).is_err());
+
+        let save = fixture(&[]);
+        assert!(SaveFile::parse(&save[..save.len() - 1]).is_err());
+
+        // Without USERDATA_11 the summaries can still be read, but not without USERDATA_10
+        let mut save = fixture(&[]);
+        save[0x0C] = 11;
+        assert!(SaveFile::parse(&save).is_ok());
+        save[0x0C] = 10;
+        assert!(SaveFile::parse(&save).is_err());
+
+        // USERDATA_10 is found by name
+        let mut save = fixture(&[]);
+        let names_offset = HEADER_SIZE + 12 * ENTRY_HEADER_SIZE;
+        let name = names_offset + 10 * 2 * "USERDATA_10\0".len() + 2 * "USERDATA_1".len();
+        save[name] = b'5';
+        assert!(SaveFile::parse(&save).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_out_of_bounds_offsets() {
+        // Header offsets that overflow, for the second entry and its fields
+        for entry_header_size in [u64::MAX, u64::MAX - HEADER_SIZE as u64] {
+            let mut save = fixture(&[]);
+            save[0x20..0x28].copy_from_slice(&entry_header_size.to_le_bytes());
+            assert!(SaveFile::parse(&save).is_err());
+        }
+
+        let mut save = fixture(&[]);
+        let header = HEADER_SIZE;
+        save[header + 0x08..header + 0x10].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(SaveFile::parse(&save).is_err());
+
+        let mut save = fixture(&[]);
+        save[header + 0x14..header + 0x18].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(SaveFile::parse(&save).is_err());
+    }
+}
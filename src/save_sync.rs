@@ -0,0 +1,338 @@
+//! Two-way sync of the DEN save between the Proton prefixes of the DEN shortcut and of
+//! Elden Ring. The hash of the last synced save tells which side changed since; when both
+//! did, the user decides which one to keep.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{stdout, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use cli_select::Select;
+use serde::{Deserialize, Serialize};
+
+use crate::config::data_dir;
+use crate::download;
+use crate::save_backup::{self, replace_save, SaveBackups, SaveLocation};
+
+const STATE_FILE: &str = "save-sync.json";
+
+/// The same save in both prefixes.
+pub struct SavePair {
+    pub den: PathBuf,
+    pub elden_ring: PathBuf,
+}
+
+impl SavePair {
+    /// Both paths, so a record of syncing other files never applies to these.
+    fn key(&self) -> String {
+        format!(
+            "{}|{}",
+            self.den.to_string_lossy(),
+            self.elden_ring.to_string_lossy()
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SaveState {
+    pub hash: String,
+    pub modified: SystemTime,
+}
+
+impl SaveState {
+    fn read(path: &Path) -> std::io::Result<Option<SaveState>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(SaveState {
+            modified: file.metadata()?.modified()?,
+            hash: download::file_sha256(&mut file)?,
+        }))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyncAction {
+    /// There is no save on either side.
+    Nothing,
+    InSync,
+    CopyToEldenRing,
+    CopyToDen,
+    /// Both saves changed since the last sync.
+    Conflict,
+}
+
+/// Hashes of the last synced save, by the paths of the pair.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+struct SyncState {
+    last_synced: HashMap<String, String>,
+}
+
+impl SyncState {
+    fn load_from(path: &Path) -> SyncState {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| {
+                serde_json::from_str(&contents)
+                    .map_err(|e| tracing::warn!("Ignoring unreadable save sync state: {}", e))
+                    .ok()
+            })
+            .unwrap_or_default()
+    }
+
+    fn save_to(&self, path: &Path) {
+        let contents = serde_json::to_string_pretty(self).expect("Failed to serialize state");
+        path.parent()
+            .map(std::fs::create_dir_all)
+            .transpose()
+            .and_then(|_| std::fs::write(path, contents))
+            .map_err(|e| tracing::warn!("Failed to write save sync state to {:?}: {}", path, e))
+            .ok();
+    }
+}
+
+fn decide(
+    den: Option<&SaveState>,
+    elden_ring: Option<&SaveState>,
+    last_synced: Option<&str>,
+) -> SyncAction {
+    match (den, elden_ring) {
+        (None, None) => SyncAction::Nothing,
+        (Some(_), None) => SyncAction::CopyToEldenRing,
+        (None, Some(_)) => SyncAction::CopyToDen,
+        (Some(den), Some(elden_ring)) if den.hash == elden_ring.hash => SyncAction::InSync,
+        (Some(den), Some(elden_ring)) => match last_synced {
+            Some(last) if last == den.hash => SyncAction::CopyToDen,
+            Some(last) if last == elden_ring.hash => SyncAction::CopyToEldenRing,
+            // Both changed, or they were never synced
+            _ => SyncAction::Conflict,
+        },
+    }
+}
+
+/// Brings both saves up to date, backing up whichever is replaced.
+pub fn sync(backups: &SaveBackups, pair: &SavePair) {
+    sync_with(
+        backups,
+        pair,
+        &data_dir().join(STATE_FILE),
+        resolve_conflict,
+    )
+}
+
+fn sync_with(
+    backups: &SaveBackups,
+    pair: &SavePair,
+    state_path: &Path,
+    resolve: impl FnOnce(&SaveState, &SaveState) -> SyncAction,
+) {
+    let (den, elden_ring) = match (
+        SaveState::read(&pair.den),
+        SaveState::read(&pair.elden_ring),
+    ) {
+        (Ok(den), Ok(elden_ring)) => (den, elden_ring),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("Failed to read saves, not syncing them: {}", e);
+            return;
+        }
+    };
+
+    let mut state = SyncState::load_from(state_path);
+    let key = pair.key();
+    let mut action = decide(
+        den.as_ref(),
+        elden_ring.as_ref(),
+        state.last_synced.get(&key).map(String::as_str),
+    );
+    if action == SyncAction::Conflict {
+        // Both exist when there is a conflict
+        if let (Some(den), Some(elden_ring)) = (&den, &elden_ring) {
+            tracing::warn!(
+                "The saves in the DEN and Elden Ring prefixes both changed since the last sync"
+            );
+            action = resolve(den, elden_ring);
+        }
+    }
+
+    let copied = match action {
+        SyncAction::Nothing | SyncAction::Conflict => return,
+        SyncAction::InSync => {
+            tracing::info!("Saves are in sync");
+            Ok(())
+        }
+        SyncAction::CopyToEldenRing => copy(
+            backups,
+            &pair.den,
+            &pair.elden_ring,
+            SaveLocation::EldenRing,
+        ),
+        SyncAction::CopyToDen => copy(backups, &pair.elden_ring, &pair.den, SaveLocation::Den),
+    };
+    match copied {
+        Ok(()) => {
+            let synced = match action {
+                SyncAction::CopyToDen => elden_ring,
+                _ => den,
+            };
+            if let Some(synced) = synced {
+                state.last_synced.insert(key, synced.hash);
+                state.save_to(state_path);
+            }
+        }
+        Err(e) => tracing::error!("Failed to sync save file: {}", e),
+    }
+}
+
+fn copy(
+    backups: &SaveBackups,
+    source: &Path,
+    destination: &Path,
+    location: SaveLocation,
+) -> std::io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    replace_save(backups, source, destination, location)?;
+    tracing::info!("Synced {:?} to {:?}", source, destination);
+    Ok(())
+}
+
+/// Asks which save to keep. Without a terminal to ask on, the newer one wins; the other
+/// is backed up before it is replaced either way.
+fn resolve_conflict(den: &SaveState, elden_ring: &SaveState) -> SyncAction {
+    let newer = if den.modified >= elden_ring.modified {
+        SyncAction::CopyToEldenRing
+    } else {
+        SyncAction::CopyToDen
+    };
+    if !std::io::stdin().is_terminal() {
+        tracing::warn!("Not running interactively, keeping the newer save");
+        return newer;
+    }
+
+    println!("\nThe DEN save differs between the DEN and Elden Ring prefixes, and both changed since they were last synced.");
+    println!("Choose the save to keep. The other one is backed up before it is replaced.");
+    println!("Use the arrow keys to navigate and Enter to select.\n");
+
+    let mut options = vec![
+        (
+            SyncAction::CopyToEldenRing,
+            format!(
+                "Keep the DEN prefix save, modified {}",
+                save_backup::display_time(den.modified)
+            ),
+        ),
+        (
+            SyncAction::CopyToDen,
+            format!(
+                "Keep the Elden Ring prefix save, modified {}",
+                save_backup::display_time(elden_ring.modified)
+            ),
+        ),
+    ];
+    // Newer first, so it is the default
+    if newer == SyncAction::CopyToDen {
+        options.swap(0, 1);
+    }
+    options.push((
+        SyncAction::Conflict,
+        "Don't sync the saves this time".to_string(),
+    ));
+
+    let labels: Vec<&String> = options.iter().map(|(_, label)| label).collect();
+    let &selected = Select::new(&labels, stdout()).start();
+    let index = labels.iter().position(|&label| label == selected);
+    index
+        .map(|i| options.swap_remove(i).0)
+        .unwrap_or(SyncAction::Conflict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn state(hash: &str, modified: u64) -> SaveState {
+        SaveState {
+            hash: hash.to_string(),
+            modified: UNIX_EPOCH + Duration::from_secs(modified),
+        }
+    }
+
+    #[test]
+    fn test_decide() {
+        let (a, b) = (state("a", 1), state("b", 2));
+        assert_eq!(decide(None, None, None), SyncAction::Nothing);
+        assert_eq!(
+            decide(Some(&a), None, Some("b")),
+            SyncAction::CopyToEldenRing
+        );
+        assert_eq!(decide(None, Some(&b), Some("a")), SyncAction::CopyToDen);
+        assert_eq!(
+            decide(Some(&a), Some(&state("a", 5)), None),
+            SyncAction::InSync
+        );
+        // Only the side that differs from the last sync changed
+        assert_eq!(decide(Some(&a), Some(&b), Some("a")), SyncAction::CopyToDen);
+        assert_eq!(
+            decide(Some(&a), Some(&b), Some("b")),
+            SyncAction::CopyToEldenRing
+        );
+        assert_eq!(decide(Some(&a), Some(&b), Some("c")), SyncAction::Conflict);
+        assert_eq!(decide(Some(&a), Some(&b), None), SyncAction::Conflict);
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let backups = SaveBackups::new(dir.path().join("backups"), 10, Duration::MAX);
+        let state_path = dir.path().join(STATE_FILE);
+        let pair = SavePair {
+            den: dir.path().join("den").join("ER0000.dentest"),
+            elden_ring: dir.path().join("eldenring").join("ER0000.dentest"),
+        };
+        let no_conflict =
+            |_: &SaveState, _: &SaveState| -> SyncAction { panic!("unexpected conflict") };
+
+        std::fs::create_dir_all(pair.den.parent().unwrap()).unwrap();
+        std::fs::write(&pair.den, b"v1").unwrap();
+        sync_with(&backups, &pair, &state_path, no_conflict);
+        assert_eq!(std::fs::read(&pair.elden_ring).unwrap(), b"v1");
+
+        // Played from the Elden Ring prefix: that save is newer and wins
+        std::fs::write(&pair.elden_ring, b"v2").unwrap();
+        sync_with(&backups, &pair, &state_path, no_conflict);
+        assert_eq!(std::fs::read(&pair.den).unwrap(), b"v2");
+        assert_eq!(std::fs::read(&backups.list()[0].path).unwrap(), b"v1");
+
+        // Both changed: the resolver decides, and declining leaves both alone
+        std::fs::write(&pair.den, b"den").unwrap();
+        std::fs::write(&pair.elden_ring, b"elden ring").unwrap();
+        sync_with(&backups, &pair, &state_path, |_, _| SyncAction::Conflict);
+        assert_eq!(std::fs::read(&pair.den).unwrap(), b"den");
+        assert_eq!(std::fs::read(&pair.elden_ring).unwrap(), b"elden ring");
+
+        sync_with(&backups, &pair, &state_path, |_, _| SyncAction::CopyToDen);
+        assert_eq!(std::fs::read(&pair.den).unwrap(), b"elden ring");
+        assert_eq!(std::fs::read(&backups.list()[0].path).unwrap(), b"den");
+
+        // A record of syncing the DEN save with another file doesn't say which side changed
+        let other = SavePair {
+            den: pair.den.clone(),
+            elden_ring: dir.path().join("other").join("ER0000.dentest"),
+        };
+        std::fs::create_dir_all(other.elden_ring.parent().unwrap()).unwrap();
+        std::fs::write(&other.elden_ring, b"older").unwrap();
+        let mut conflicts = 0;
+        sync_with(&backups, &other, &state_path, |_, _| {
+            conflicts += 1;
+            SyncAction::Conflict
+        });
+        assert_eq!(conflicts, 1);
+        assert_eq!(std::fs::read(&pair.den).unwrap(), b"elden ring");
+    }
+}
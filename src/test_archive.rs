@@ -0,0 +1,39 @@
+//! Builds release zips, signed or not, for updater tests.
+
+use std::io::{Cursor, Write};
+
+use zip::write::SimpleFileOptions;
+use zipsign_api::SigningKey;
+
+/// A fixed key, so signatures are reproducible across test runs.
+pub fn test_key(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32])
+}
+
+pub fn public_key(key: &SigningKey) -> [u8; zipsign_api::PUBLIC_KEY_LENGTH] {
+    key.verifying_key().to_bytes()
+}
+
+pub fn zip_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        writer
+            .start_file(*name, SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(contents).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+/// Signs `zip` the way release archives are signed, using the asset name as context.
+pub fn sign_zip(zip: &[u8], key: &SigningKey, asset_name: &str) -> Vec<u8> {
+    let mut signed = Cursor::new(Vec::new());
+    zipsign_api::sign::copy_and_sign_zip(
+        &mut Cursor::new(zip),
+        &mut signed,
+        std::slice::from_ref(key),
+        Some(asset_name.as_bytes()),
+    )
+    .unwrap();
+    signed.into_inner()
+}
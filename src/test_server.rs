@@ -0,0 +1,145 @@
+//! Minimal HTTP/1.1 server standing in for GitHub and download hosts in tests.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug)]
+pub struct TestRequest {
+    /// Request target as sent, e.g. `/releases?page=2`.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl TestRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Drop the connection after sending this many body bytes.
+    pub truncate_at: Option<usize>,
+}
+
+impl TestResponse {
+    pub fn ok(body: impl Into<Vec<u8>>) -> TestResponse {
+        TestResponse {
+            status: 200,
+            headers: Vec::new(),
+            body: body.into(),
+            truncate_at: None,
+        }
+    }
+
+    pub fn status(status: u16) -> TestResponse {
+        TestResponse {
+            status,
+            ..TestResponse::ok(Vec::new())
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> TestResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn truncated_at(mut self, bytes: usize) -> TestResponse {
+        self.truncate_at = Some(bytes);
+        self
+    }
+}
+
+pub struct TestServer {
+    url: String,
+    requests: Arc<Mutex<Vec<TestRequest>>>,
+}
+
+impl TestServer {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Requests received so far, in arrival order.
+    pub fn requests(&self) -> Vec<TestRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Starts a server on an ephemeral port. The handler receives each request along with
+/// the server's base URL, so it can build absolute links back to itself.
+pub fn serve<F>(handler: F) -> TestServer
+where
+    F: Fn(&TestRequest, &str) -> TestResponse + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test server");
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+
+    let handler = Arc::new(handler);
+    let base_url = url.clone();
+    let received = requests.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let handler = handler.clone();
+            let base_url = base_url.clone();
+            let received = received.clone();
+            std::thread::spawn(move || {
+                if let Some(request) = read_request(&stream) {
+                    received.lock().unwrap().push(request.clone());
+                    write_response(stream, handler(&request, &base_url));
+                }
+            });
+        }
+    });
+
+    TestServer { url, requests }
+}
+
+fn read_request(stream: &TcpStream) -> Option<TestRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let path = line.split_whitespace().nth(1)?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    Some(TestRequest { path, headers })
+}
+
+fn write_response(mut stream: TcpStream, response: TestResponse) {
+    let mut head = format!(
+        "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    let body = match response.truncate_at {
+        Some(limit) => &response.body[..limit.min(response.body.len())],
+        None => &response.body[..],
+    };
+    stream.write_all(head.as_bytes()).ok();
+    stream.write_all(body).ok();
+    stream.flush().ok();
+}
@@ -0,0 +1,168 @@
+//! The GitHub token used to read releases of a private repository.
+//!
+//! The token is only ever read at runtime, never compiled in, from the first of:
+//!
+//! 1. the `REPO_PRIVATE_KEY` environment variable, which may also be set in a `.env` file
+//!    in the working directory,
+//! 2. the `github-token` file in `%APPDATA%\denlauncher`,
+//! 3. a generic `denlauncher/github` credential in the Windows Credential Manager.
+//!
+//! Under Proton, `%APPDATA%` and the Credential Manager are those of the Wine prefix;
+//! the Linux user's own config and keyring can't be reached.
+//!
+//! Whatever token is found is redacted from the log, see [`redact`].
+
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+
+use windows::core::PCWSTR;
+use windows::Win32::Security::Credentials::{CredFree, CredReadW, CREDENTIALW, CRED_TYPE_GENERIC};
+
+const TOKEN_ENV: &str = "REPO_PRIVATE_KEY";
+const TOKEN_FILE: &str = "github-token";
+const CREDENTIAL_TARGET: &str = "denlauncher/github";
+const REDACTED: &str = "[redacted]";
+
+/// Prefixes of GitHub's token formats, redacted even if the token didn't come from us.
+const GITHUB_TOKEN_PREFIXES: &[&str] = &["github_pat_", "ghp_", "gho_", "ghu_", "ghs_", "ghr_"];
+
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+static GITHUB_TOKEN: LazyLock<Option<String>> = LazyLock::new(|| {
+    let (token, source) = from_env()
+        .map(|token| (token, "the environment"))
+        .or_else(|| {
+            user_config_dir()
+                .and_then(|dir| from_file(&dir.join(TOKEN_FILE)))
+                .map(|token| (token, "the user config"))
+        })
+        .or_else(|| from_credential_manager().map(|token| (token, "the Credential Manager")))?;
+    add_secret(&token);
+    tracing::debug!("Using the GitHub token from {}", source);
+    Some(token)
+});
+
+pub fn github_token() -> Option<&'static str> {
+    GITHUB_TOKEN.as_deref()
+}
+
+fn normalize(token: &str) -> Option<String> {
+    let token = token.trim();
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+fn from_env() -> Option<String> {
+    normalize(&std::env::var(TOKEN_ENV).ok()?)
+}
+
+fn from_file(path: &Path) -> Option<String> {
+    normalize(&std::fs::read_to_string(path).ok()?)
+}
+
+fn user_config_dir() -> Option<PathBuf> {
+    Some(PathBuf::from(std::env::var_os("APPDATA")?).join("denlauncher"))
+}
+
+fn from_credential_manager() -> Option<String> {
+    let target: Vec<u16> = CREDENTIAL_TARGET.encode_utf16().chain([0]).collect();
+    let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
+    unsafe {
+        CredReadW(
+            PCWSTR(target.as_ptr()),
+            CRED_TYPE_GENERIC,
+            None,
+            &mut credential,
+        )
+        .ok()?;
+        let size = (*credential).CredentialBlobSize as usize;
+        let token = if size == 0 || (*credential).CredentialBlob.is_null() {
+            None
+        } else {
+            let blob = std::slice::from_raw_parts((*credential).CredentialBlob, size);
+            std::str::from_utf8(blob).ok().and_then(normalize)
+        };
+        CredFree(credential as *const _);
+        token
+    }
+}
+
+/// Makes `redact` hide `secret` from now on.
+pub fn add_secret(secret: &str) {
+    if let Ok(mut secrets) = SECRETS.write() {
+        if !secrets.iter().any(|known| known == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+/// `text` with every known secret and anything shaped like a GitHub token replaced.
+pub fn redact(text: &str) -> String {
+    let mut text = text.to_string();
+    if let Ok(secrets) = SECRETS.read() {
+        for secret in secrets.iter() {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+    }
+    redact_github_tokens(&text)
+}
+
+fn redact_github_tokens(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((start, prefix)) = GITHUB_TOKEN_PREFIXES
+        .iter()
+        .filter_map(|prefix| rest.find(prefix).map(|start| (start, prefix)))
+        .min()
+    {
+        let body = &rest[start + prefix.len()..];
+        let len = body
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(body.len());
+        // Part of a longer word, not a token
+        let in_word = rest[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+        if len == 0 || in_word {
+            redacted.push_str(&rest[..start + prefix.len()]);
+            rest = body;
+        } else {
+            redacted.push_str(&rest[..start]);
+            redacted.push_str(REDACTED);
+            rest = &body[len..];
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(TOKEN_FILE);
+        assert_eq!(from_file(&path), None);
+        std::fs::write(&path, "  secret-token\n").unwrap();
+        assert_eq!(from_file(&path).as_deref(), Some("secret-token"));
+        std::fs::write(&path, "\n").unwrap();
+        assert_eq!(from_file(&path), None);
+    }
+
+    #[test]
+    fn test_redact() {
+        add_secret("s3cr3t-value");
+        assert_eq!(
+            redact("Authorization: token s3cr3t-value"),
+            "Authorization: token [redacted]"
+        );
+        assert_eq!(
+            redact("token ghp_abc123XYZ, then github_pat_11AB_cd."),
+            "token [redacted], then [redacted]."
+        );
+        // Only whole tokens are redacted
+        assert_eq!(redact("ghp_ nightghp_abc"), "ghp_ nightghp_abc");
+    }
+}
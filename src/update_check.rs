@@ -0,0 +1,209 @@
+//! Background update checks. The release check runs on its own thread while the launcher
+//! carries on, and an update it finds is cached and offered on the next launch.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{data_dir, write_atomically};
+use crate::release_source::Release;
+use crate::updater::UpdateError;
+
+const CACHE_FILE: &str = "update-check.json";
+
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+pub const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An update found by an earlier check, waiting to be offered.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PendingUpdate {
+    pub release: Release,
+    /// Rendered release notes of every version since the one installed when found.
+    pub notes: String,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(default)]
+pub struct UpdateCache {
+    /// When releases were last fetched successfully, in seconds since the epoch.
+    pub last_checked: Option<u64>,
+    /// When a check last started, whether or not it finished.
+    pub last_attempted: Option<u64>,
+    pub pending: Option<PendingUpdate>,
+}
+
+impl UpdateCache {
+    pub fn load() -> UpdateCache {
+        UpdateCache::load_from(&cache_path())
+    }
+
+    fn load_from(path: &Path) -> UpdateCache {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| {
+                serde_json::from_str(&contents)
+                    .map_err(|e| tracing::warn!("Ignoring unreadable update cache: {}", e))
+                    .ok()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        self.save_to(&cache_path());
+    }
+
+    fn save_to(&self, path: &Path) {
+        let contents = serde_json::to_string_pretty(self).expect("Failed to serialize cache");
+        write_atomically(path, contents.as_bytes())
+            .map_err(|e| tracing::warn!("Failed to write update cache to {:?}: {}", path, e))
+            .ok();
+    }
+
+    /// Whether `interval` has passed since the last check. Failed checks count too, so
+    /// launches don't each wait out the timeout while the source can't be reached.
+    pub fn is_due(&self, interval: Duration, now: SystemTime) -> bool {
+        let Some(last) = self.last_checked.max(self.last_attempted) else {
+            return true;
+        };
+        now.duration_since(UNIX_EPOCH + Duration::from_secs(last))
+            .map_or(true, |elapsed| elapsed >= interval)
+    }
+
+    pub fn record_attempt(&mut self, now: SystemTime) {
+        self.last_attempted = epoch_secs(now);
+    }
+
+    pub fn record_check(&mut self, now: SystemTime, pending: Option<PendingUpdate>) {
+        self.last_checked = epoch_secs(now);
+        self.pending = pending;
+    }
+}
+
+fn epoch_secs(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+fn cache_path() -> PathBuf {
+    data_dir().join(CACHE_FILE)
+}
+
+/// A release check running in the background.
+pub struct UpdateCheck {
+    result: Receiver<Result<Option<String>, UpdateError>>,
+    deadline: Instant,
+    timeout: Duration,
+}
+
+impl UpdateCheck {
+    /// Runs `check` on its own thread. It returns the version it found, if any.
+    pub fn spawn<F>(timeout: Duration, check: F) -> UpdateCheck
+    where
+        F: FnOnce() -> Result<Option<String>, UpdateError> + Send + 'static,
+    {
+        let (sender, result) = mpsc::channel();
+        std::thread::spawn(move || sender.send(check()).ok());
+        UpdateCheck {
+            result,
+            deadline: Instant::now() + timeout,
+            timeout,
+        }
+    }
+
+    /// Waits for the check until its time budget runs out, then reports how it went.
+    /// A check still running is left to finish, or not, in the background.
+    pub fn finish(self) {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        match self.result.recv_timeout(remaining) {
+            Ok(Ok(Some(version))) => {
+                tracing::info!(
+                    "DEN {} is available and will be offered on the next launch",
+                    version
+                )
+            }
+            Ok(Ok(None)) => tracing::info!("No updates found"),
+            Ok(Err(err)) => tracing::warn!("Update check failed: {}", err),
+            Err(RecvTimeoutError::Timeout) => tracing::warn!(
+                "Update check did not finish within {:?}, continuing without it",
+                self.timeout
+            ),
+            Err(RecvTimeoutError::Disconnected) => {
+                tracing::warn!("Update check stopped unexpectedly")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_due() {
+        let now = UNIX_EPOCH + Duration::from_secs(10_000);
+        let mut cache = UpdateCache::default();
+        assert!(cache.is_due(DEFAULT_CHECK_INTERVAL, now));
+
+        cache.record_check(now, None);
+        assert!(!cache.is_due(DEFAULT_CHECK_INTERVAL, now + Duration::from_secs(60)));
+        assert!(cache.is_due(DEFAULT_CHECK_INTERVAL, now + DEFAULT_CHECK_INTERVAL));
+        // A clock set back doesn't postpone checks forever
+        assert!(cache.is_due(DEFAULT_CHECK_INTERVAL, now - Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_failed_check_is_not_retried_next_launch() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(CACHE_FILE);
+        let now = UNIX_EPOCH + Duration::from_secs(10_000);
+
+        // The check started but never recorded a result
+        let mut cache = UpdateCache::default();
+        cache.record_attempt(now);
+        cache.save_to(&path);
+
+        let next_launch = now + Duration::from_secs(60);
+        let loaded = UpdateCache::load_from(&path);
+        assert_eq!(loaded.last_checked, None);
+        assert!(!loaded.is_due(DEFAULT_CHECK_INTERVAL, next_launch));
+        assert!(loaded.is_due(DEFAULT_CHECK_INTERVAL, now + DEFAULT_CHECK_INTERVAL));
+    }
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(CACHE_FILE);
+        let mut cache = UpdateCache::default();
+        cache.record_check(
+            UNIX_EPOCH + Duration::from_secs(42),
+            Some(PendingUpdate {
+                release: serde_json::from_str(r#"{"tag_name":"v2.1.0","assets":[]}"#).unwrap(),
+                notes: "notes".to_string(),
+            }),
+        );
+        cache.save_to(&path);
+
+        // Saving again replaces the file through a temporary one, leaving nothing else
+        cache.save_to(&path);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let loaded = UpdateCache::load_from(&path);
+        assert_eq!(loaded.last_checked, Some(42));
+        assert_eq!(loaded.pending.unwrap().release.tag_name, "v2.1.0");
+        assert!(UpdateCache::load_from(&dir.path().join("missing.json"))
+            .pending
+            .is_none());
+    }
+
+    #[test]
+    fn test_check_times_out() {
+        let check = UpdateCheck::spawn(Duration::from_millis(50), || {
+            std::thread::sleep(Duration::from_secs(5));
+            Ok(None)
+        });
+        let started = Instant::now();
+        check.finish();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
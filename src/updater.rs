@@ -1,268 +1,1153 @@
-use std::io::{Seek, Write};
+use std::ffi::{OsStr, OsString};
+use std::io::{stdout, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::{fmt, str::FromStr};
 
 use crate::{
-    constants::{CONTENT_DIR, DLL_NAME, ELDENRING_EXE, REPO_NAME, REPO_OWNER, REPO_PRIVATE_KEY},
+    changelog,
+    config::{data_dir, exe_dir, Config},
+    constants::ELDENRING_EXE,
+    download::{self, RetryPolicy, DEFAULT_DOWNLOAD_ATTEMPTS},
+    extract,
     injector::{get_pids_by_name, kill_process},
+    install::Install,
+    keyring::{self, Keyring, PublicKey, KEYRING_ASSET},
+    manifest::{ReleaseMetadata, RELEASE_METADATA_FILE},
+    release_cache::ReleaseCache,
+    release_source::{self, Release, ReleaseAsset, ReleaseSource, SourceConfig},
+    update_check::{self, PendingUpdate, UpdateCache, UpdateCheck},
 };
 
-use const_format::formatcp;
+use cli_select::Select;
 use semver::Version;
-use serde::Deserialize;
-use walkdir::WalkDir;
-use zip::ZipArchive;
+use serde::{Deserialize, Serialize};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-#[derive(Deserialize, Clone)]
-struct ReleaseAsset {
-    pub url: String,
-    pub name: String,
-}
+/// Marks a launch started by `relaunch`, so the new version goes straight on to the
+/// game instead of looking for updates again.
+pub const RELAUNCHED_FLAG: &str = "--relaunched";
 
-#[derive(Deserialize, Clone)]
-struct Release {
-    pub tag_name: String,
-    pub assets: Vec<ReleaseAsset>,
+/// Release channels, ordered from most to least conservative.
+/// Each channel also receives every release of the channels before it.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stable,
+    Rc,
+    Beta,
 }
 
-pub fn bump_is_greater(current: &str, other: &str) -> Option<bool> {
-    Some(Version::parse(other).ok()? > Version::parse(current).ok()?)
+impl Channel {
+    /// Classifies a version by its pre-release identifier: none is stable, `rc*` is rc,
+    /// anything else (`beta`, `alpha`, ...) is beta.
+    fn of_version(version: &Version) -> Channel {
+        match version.pre.as_str() {
+            "" => Channel::Stable,
+            pre if pre.starts_with("rc") => Channel::Rc,
+            _ => Channel::Beta,
+        }
+    }
+
+    pub(crate) fn accepts(self, version: &Version) -> bool {
+        Channel::of_version(version) <= self
+    }
+
+    /// Channel of the running build, used when none is configured.
+    pub fn installed() -> Channel {
+        Version::parse(VERSION)
+            .map(|v| Channel::of_version(&v))
+            .unwrap_or(Channel::Stable)
+    }
 }
 
-fn get_update() -> Option<Release> {
-    let mut request = ureq::get(&format!(
-        "https://api.github.com/repos/{}/{}/releases",
-        *REPO_OWNER, *REPO_NAME
-    ))
-    .set("User-Agent", formatcp!("denlauncher/{}", VERSION))
-    .query("per_page", "20");
+impl FromStr for Channel {
+    type Err = String;
 
-    if !REPO_PRIVATE_KEY.is_empty() {
-        request = request.set("Authorization", &format!("token {}", *REPO_PRIVATE_KEY));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "stable" => Ok(Channel::Stable),
+            "rc" => Ok(Channel::Rc),
+            "beta" => Ok(Channel::Beta),
+            other => Err(format!(
+                "Unknown release channel '{other}', expected stable, rc or beta"
+            )),
+        }
     }
+}
 
-    let response = request
-        .call()
-        .map_err(|e| {
-            tracing::error!("Failed to fetch releases: {}", e);
-            e
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Channel::Stable => "stable",
+            Channel::Rc => "rc",
+            Channel::Beta => "beta",
         })
-        .ok()?
-        .into_json::<Vec<Release>>()
-        .map_err(|e| {
-            tracing::error!("Failed to parse JSON: {}", e);
-            e
+    }
+}
+
+/// What to do about an available update.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateDecision {
+    Install,
+    /// Never offer this version again; newer ones still are.
+    Skip,
+    /// Launch without updating, ask again next launch.
+    Postpone,
+}
+
+impl fmt::Display for UpdateDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UpdateDecision::Install => "install",
+            UpdateDecision::Skip => "skip",
+            UpdateDecision::Postpone => "postpone",
         })
-        .ok()?;
+    }
+}
 
-    response
+/// Why an update didn't go through. Every kind but `SelfReplace` fails before the
+/// installed version is touched.
+#[derive(Debug)]
+pub enum UpdateError {
+    /// Releases or the archive couldn't be fetched, or arrived incomplete.
+    Network(String),
+    /// The archive isn't signed by a trusted key or isn't the release it claims to be.
+    Signature(String),
+    /// The archive couldn't be unpacked.
+    Extraction(String),
+    /// Local files couldn't be read or written while preparing the update.
+    Filesystem(String),
+    /// Swapping in the new version failed. The previous install is restored, but that
+    /// may not have fully worked either.
+    SelfReplace(String),
+}
+
+impl UpdateError {
+    /// Whether the installed version is known to be intact, so the launcher can go on
+    /// with it.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, UpdateError::SelfReplace(_))
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::Network(err) => write!(f, "Network error: {err}"),
+            UpdateError::Signature(err) => write!(f, "Archive verification failed: {err}"),
+            UpdateError::Extraction(err) => write!(f, "Failed to extract archive: {err}"),
+            UpdateError::Filesystem(err) => write!(f, "File system error: {err}"),
+            UpdateError::SelfReplace(err) => write!(f, "Failed to install update: {err}"),
+        }
+    }
+}
+
+/// Reports a failed update. The launcher carries on with the installed version unless
+/// the failed install may have left it broken.
+fn handle_update_error(err: UpdateError) {
+    if err.is_recoverable() {
+        tracing::error!("{}. Continuing with the installed version {}", err, VERSION);
+    } else {
+        panic!(
+            "{}. Run the launcher with --rollback to restore the previous version",
+            err
+        );
+    }
+}
+
+pub fn bump_is_greater(current: &str, other: &str, channel: Channel) -> Option<bool> {
+    let other = Version::parse(other).ok()?;
+    let current = Version::parse(current).ok()?;
+    Some(channel.accepts(&other) && other > current)
+}
+
+/// Picks the highest eligible release regardless of the order the API listed them in.
+/// Releases sharing a version are ordered by build metadata (`+patch.2` < `+patch.10`),
+/// then by tag name, so the choice never depends on API ordering.
+fn select_update(releases: Vec<Release>, current: &str, channel: Channel) -> Option<Release> {
+    releases
         .into_iter()
-        .find(|r| bump_is_greater(VERSION, r.tag_name.trim_start_matches("v")).unwrap_or(false))
+        .filter(|r| r.is_on_channel(channel))
+        .filter_map(|r| Some((r.version()?, r)))
+        .filter(|(_, r)| bump_is_greater(current, r.version_str(), channel).unwrap_or(false))
+        .max_by(|(a, ra), (b, rb)| a.cmp(b).then_with(|| ra.tag_name.cmp(&rb.tag_name)))
+        .map(|(_, r)| r)
 }
 
 // #[cfg(not(debug_assertions))]
+/// Fails if no key in `keys` signed the archive, including when every key was revoked.
 fn verify_signature(
     archive: &mut std::fs::File,
     context: &[u8],
-    keys: &[[u8; zipsign_api::PUBLIC_KEY_LENGTH]],
+    keys: &[PublicKey],
 ) -> Result<(), zipsign_api::ZipsignError> {
-    if keys.is_empty() {
-        return Ok(());
-    }
-
     tracing::info!("Verifying signature of update archive");
 
-    let keys = keys.iter().copied().map(Ok);
-    let keys = zipsign_api::verify::collect_keys(keys).map_err(zipsign_api::ZipsignError::from)?;
+    let verifying_keys = keys.iter().copied().map(Ok);
+    let verifying_keys = zipsign_api::verify::collect_keys(verifying_keys)
+        .map_err(zipsign_api::ZipsignError::from)?;
 
-    zipsign_api::verify::verify_zip(archive, &keys, Some(context))
+    let index = zipsign_api::verify::verify_zip(archive, &verifying_keys, Some(context))
         .map_err(zipsign_api::ZipsignError::from)?;
+    tracing::info!(
+        "Update archive signed by release key {}",
+        keyring::fingerprint(&keys[index])
+    );
     Ok(())
 }
 
-fn update_from_asset(asset: &ReleaseAsset) {
+fn update_from_asset(
+    source: &dyn ReleaseSource,
+    release: &Release,
+    asset: &ReleaseAsset,
+    retry: RetryPolicy,
+    keyring: &Keyring,
+    cache: Option<&ReleaseCache>,
+    expected: &ReleaseExpectation,
+) -> Result<PathBuf, UpdateError> {
+    let (tmp_archive, tmp_dir) = download_asset(source, release, asset, retry, keyring, cache)?;
+
     for pid in get_pids_by_name(ELDENRING_EXE) {
         kill_process(pid);
     }
 
-    let (exe_dir, content_dir, dll_path) = get_paths();
-    let (tmp_archive, tmp_dir) = download_asset(asset);
-    remove_old_content(content_dir, dll_path);
-    extract_archive(&tmp_archive, &tmp_dir);
-    perform_binary_replacement(&tmp_dir, exe_dir);
+    extract_archive(&tmp_archive, &tmp_dir)?;
+    drop(tmp_archive);
+    std::fs::remove_file(partial_download_path(release, asset))
+        .map_err(|e| tracing::warn!("Failed to remove downloaded archive: {}", e))
+        .ok();
+
+    install_extracted(&tmp_dir, expected)
 }
 
-fn get_paths() -> (std::path::PathBuf, std::path::PathBuf, std::path::PathBuf) {
-    let current_exe = std::env::current_exe().unwrap();
-    let exe_dir = current_exe.parent().unwrap().to_path_buf();
-    let content_dir = exe_dir.join(&*CONTENT_DIR);
-    let dll_path = content_dir.join(&*DLL_NAME);
-    (exe_dir, content_dir, dll_path)
+/// What the metadata inside a release archive has to agree with before it is installed.
+/// The metadata is covered by the archive signature, so an older release can't be
+/// replayed under a newer tag.
+struct ReleaseExpectation {
+    /// Version of the tag the archive was published under, if it came from a release.
+    version: Option<String>,
+    /// The user asked for this release specifically, newer or not.
+    allow_downgrade: bool,
 }
 
-fn download_asset(asset: &ReleaseAsset) -> (std::fs::File, tempfile::TempDir) {
-    let tmp_archive_dir = tempfile::TempDir::new().expect("Failed to create temp dir");
-    let mut tmp_archive_file = tempfile::tempfile().expect("Failed to create temp file");
+impl ReleaseExpectation {
+    fn check(&self, extracted: &Path, installed: &str) -> Result<(), String> {
+        let Some(metadata) = ReleaseMetadata::load(extracted)? else {
+            if self.allow_downgrade {
+                tracing::warn!("Release has no metadata, installing it as requested");
+                return Ok(());
+            }
+            return Err("archive has no release metadata".to_string());
+        };
 
-    tracing::info!("Downloading archive: {}", asset.url);
+        let version = Version::parse(&metadata.version)
+            .map_err(|e| format!("invalid version in release metadata: {e}"))?;
+        if let Some(expected) = &self.version {
+            let expected = Version::parse(expected.trim_start_matches('v'))
+                .map_err(|e| format!("invalid release version {expected}: {e}"))?;
+            if version != expected {
+                return Err(format!(
+                    "archive holds version {version} but was published as {expected}"
+                ));
+            }
+        }
+        if !self.allow_downgrade {
+            let installed = Version::parse(installed)
+                .map_err(|e| format!("invalid installed version {installed}: {e}"))?;
+            if version <= installed {
+                return Err(format!(
+                    "archive holds version {version}, which is not newer than {installed}"
+                ));
+            }
+        }
+        metadata.check_files(extracted)?;
 
-    let mut request = ureq::get(&asset.url)
-        .set(
-            "User-Agent",
-            formatcp!("denlauncher/{}", env!("CARGO_PKG_VERSION")),
-        )
-        .set("Accept", "application/octet-stream");
-    if !REPO_PRIVATE_KEY.is_empty() {
-        request = request.set("Authorization", &format!("token {}", *REPO_PRIVATE_KEY));
+        tracing::info!("Release {} built {}", metadata.version, metadata.build_date);
+        Ok(())
     }
-    let mut buf = Vec::new();
-    let response = request.call().expect("Failed to download archive");
+}
+
+/// Stages an extracted release and swaps it in, keeping the current install on failure.
+/// Returns the path of the installed launcher.
+fn install_extracted(
+    tmp_dir: &tempfile::TempDir,
+    expected: &ReleaseExpectation,
+) -> Result<PathBuf, UpdateError> {
+    expected
+        .check(tmp_dir.path(), VERSION)
+        .map_err(|err| UpdateError::Signature(format!("refusing to install update: {err}")))?;
+    std::fs::remove_file(tmp_dir.path().join(RELEASE_METADATA_FILE)).ok();
+
+    let install = Install::current();
+    install
+        .stage(tmp_dir.path())
+        .map_err(|e| UpdateError::Filesystem(format!("failed to stage update: {e}")))?;
+    install.commit(VERSION).map_err(UpdateError::SelfReplace)?;
+    Ok(install.exe_path().to_path_buf())
+}
 
-    if response.status() != 200 {
-        panic!("Failed to download archive: HTTP {}", response.status());
+/// Runs the freshly installed launcher with the arguments and environment this one got,
+/// waits for it and exits with its status, so Steam keeps tracking a single launch.
+fn relaunch(exe: &Path) -> ! {
+    tracing::info!("Update complete, starting the new version");
+    match std::process::Command::new(exe)
+        .args(relaunch_args(std::env::args_os()))
+        .status()
+    {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(err) => {
+            tracing::error!(
+                "Failed to start the new version, please restart the launcher: {}",
+                err
+            );
+            std::thread::sleep(std::time::Duration::from_secs(5));
+            std::process::exit(0);
+        }
     }
+}
 
-    response
-        .into_reader()
-        .read_to_end(&mut buf)
-        .expect("Failed to read archive content");
+fn relaunch_args(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    args.skip(1)
+        .filter(|arg| arg != RELAUNCHED_FLAG)
+        .chain([OsString::from(RELAUNCHED_FLAG)])
+        .collect()
+}
+
+/// Installs a release zip from disk, for venues without internet. The archive goes
+/// through the same signature check as a downloaded one, with its file name as the
+/// signature context, so it must keep the name it was released under. Unless
+/// `allow_downgrade` is set, it also has to be newer than the running version.
+pub fn update_from_file(path: &Path, allow_downgrade: bool) {
+    let name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
 
-    tmp_archive_file
-        .write_all(&buf)
-        .expect("Failed to write to temp file");
+    let mut archive = std::fs::File::open(path).unwrap_or_else(|e| {
+        tracing::error!("Failed to open {:?}: {}", path, e);
+        std::thread::sleep(std::time::Duration::from_secs(10));
+        std::process::exit(1);
+    });
 
-    // Rewind the file cursor to the beginning before verifying the signature
-    tmp_archive_file
-        .seek(std::io::SeekFrom::Start(0))
-        .expect("Failed to seek to start of temp file");
+    let mut keyring = Keyring::load();
+    if let Some(statements) = keyring::statements_beside(path) {
+        if keyring.add_statements(statements) {
+            keyring.save();
+        }
+    }
 
-    tracing::info!("Downloaded archive: {:?}", tmp_archive_file);
+    if let Err(err) = verify_signature(&mut archive, name.as_bytes(), keyring.keys()) {
+        tracing::error!(
+            "Refusing to install {:?}, it is not a DEN release signed under this file name: {}",
+            path,
+            err
+        );
+        std::thread::sleep(std::time::Duration::from_secs(10));
+        std::process::exit(1);
+    }
 
-    verify_signature(
-        &mut tmp_archive_file,
-        asset.name.as_bytes(),
-        &[*include_bytes!("../release_public_key.bin")],
-    )
-    .expect("Failed to verify update archive signature");
+    for pid in get_pids_by_name(ELDENRING_EXE) {
+        kill_process(pid);
+    }
 
-    (tmp_archive_file, tmp_archive_dir)
+    let expected = ReleaseExpectation {
+        version: None,
+        allow_downgrade,
+    };
+    let installed = temp_dir().and_then(|tmp_dir| {
+        extract_archive(&archive, &tmp_dir)?;
+        install_extracted(&tmp_dir, &expected)
+    });
+    match installed {
+        Ok(exe) => {
+            tracing::info!("Installed {}", name);
+            relaunch(&exe);
+        }
+        Err(err) => handle_update_error(err),
+    }
 }
 
-fn extract_archive(tmp_archive: &std::fs::File, temp_dir: &tempfile::TempDir) {
-    tracing::debug!("Extracting archive to: {:?}", tmp_archive);
-
-    ZipArchive::new(tmp_archive)
-        .expect("Failed to open archive")
-        .extract(temp_dir)
-        .expect("Failed to extract archive");
+fn downloads_dir() -> PathBuf {
+    data_dir().join("downloads")
 }
 
-fn remove_old_content(content_dir: std::path::PathBuf, dll_path: std::path::PathBuf) {
-    tracing::info!("Removing old content");
-    std::fs::remove_file(&dll_path)
-        .map_err(|e| tracing::warn!("Failed to remove old DLL at {:?}: {}", &dll_path, e))
-        .ok();
-    // remove content dir if it's empty
-    std::fs::read_dir(&content_dir)
-        .map(|dir| dir.count())
-        .map(|count| {
-            if count == 0 {
-                std::fs::remove_dir(&content_dir)
-                    .map_err(|e| {
-                        tracing::warn!(
-                            "Failed to remove old content dir at {:?}: {}",
-                            &content_dir,
-                            e
-                        )
-                    })
-                    .ok();
+/// Where `asset` is downloaded to. Assets of different releases often share a name, so
+/// the path includes the tag to never resume one release's download with another's.
+fn partial_download_path(release: &Release, asset: &ReleaseAsset) -> PathBuf {
+    let tag: String = release
+        .tag_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._+-".contains(c) {
+                c
+            } else {
+                '_'
             }
         })
-        .ok();
+        .collect();
+    let name = Path::new(&asset.name)
+        .file_name()
+        .unwrap_or(OsStr::new("update.zip"));
+    let mut name = name.to_os_string();
+    name.push(".part");
+    downloads_dir().join(tag).join(name)
 }
 
-fn perform_binary_replacement(tmp_dir: &tempfile::TempDir, exe_dir: std::path::PathBuf) {
-    let mut new_exe_path: Option<std::path::PathBuf> = None;
+/// Removes partial downloads of other releases than the one in `current`.
+fn remove_stale_downloads(downloads: &Path, current: &Path) {
+    let Ok(entries) = std::fs::read_dir(downloads) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path == current {
+            continue;
+        }
+        tracing::info!("Removing stale partial download {:?}", path);
+        let removed = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        removed
+            .map_err(|e| tracing::warn!("Failed to remove {:?}: {}", path, e))
+            .ok();
+    }
+}
 
-    for entry in WalkDir::new(tmp_dir).into_iter().filter_map(Result::ok) {
-        let path = entry.path();
-        let relative_path = path.strip_prefix(tmp_dir).expect("Failed to strip prefix");
-        let target_path = exe_dir.join(relative_path);
+fn download_asset(
+    source: &dyn ReleaseSource,
+    release: &Release,
+    asset: &ReleaseAsset,
+    retry: RetryPolicy,
+    keyring: &Keyring,
+    cache: Option<&ReleaseCache>,
+) -> Result<(std::fs::File, tempfile::TempDir), UpdateError> {
+    let tmp_archive_dir = temp_dir()?;
+    let partial_path = partial_download_path(release, asset);
+    if let Some(parent) = partial_path.parent() {
+        remove_stale_downloads(&downloads_dir(), parent);
+        std::fs::create_dir_all(parent).map_err(|e| {
+            UpdateError::Filesystem(format!("failed to create download dir {parent:?}: {e}"))
+        })?;
+    }
 
-        if entry.file_type().is_file() {
-            if let Some(path) = handle_file_entry(entry, &target_path) {
-                new_exe_path = Some(path);
-            }
-        } else if entry.file_type().is_dir() {
-            std::fs::create_dir_all(&target_path).expect("Failed to create directory");
-        }
+    if let Some(archive_file) =
+        cache.and_then(|cache| fetch_cached(cache, asset, &partial_path, keyring))
+    {
+        return Ok((archive_file, tmp_archive_dir));
     }
 
-    if let Some(new_exe_path) = new_exe_path {
-        tracing::info!("Replacing binary with new version");
-        self_replace::self_replace(new_exe_path).expect("Failed to replace binary");
-    } else {
-        self_replace::self_delete().expect("Failed to delete updater");
+    tracing::info!("Downloading archive: {}", asset.url);
+
+    let (mut archive_file, expected_len) = source
+        .download(asset, &partial_path, retry)
+        .map_err(UpdateError::Network)?;
+
+    let (written, digest) = archive_file
+        .metadata()
+        .map(|metadata| metadata.len())
+        .and_then(|written| Ok((written, download::file_sha256(&mut archive_file)?)))
+        .map_err(|e| UpdateError::Filesystem(format!("failed to read downloaded archive: {e}")))?;
+
+    // A bad partial download would fail the same way on every resume, so start over next time
+    if let Err(err) = check_download(asset, expected_len, written, &digest) {
+        std::fs::remove_file(&partial_path).ok();
+        return Err(UpdateError::Network(err));
+    }
+
+    tracing::info!("Downloaded archive: {:?}", partial_path);
+
+    if let Err(err) = verify_signature(&mut archive_file, asset.name.as_bytes(), keyring.keys()) {
+        std::fs::remove_file(&partial_path).ok();
+        return Err(UpdateError::Signature(format!("{err:?}")));
+    }
+
+    if let Some(cache) = cache {
+        cache.store(&asset.name, &digest, &partial_path);
     }
+
+    Ok((archive_file, tmp_archive_dir))
 }
 
-fn handle_file_entry(
-    entry: walkdir::DirEntry,
-    target_path: &std::path::PathBuf,
-) -> Option<std::path::PathBuf> {
-    // returns path if it's the current exe, otherwise copies the file
-    if entry.file_name()
-        == std::env::current_exe()
-            .expect("Failed to get current exe name")
-            .file_name()
-            .unwrap()
+fn temp_dir() -> Result<tempfile::TempDir, UpdateError> {
+    tempfile::TempDir::new()
+        .map_err(|e| UpdateError::Filesystem(format!("failed to create temp dir: {e}")))
+}
+
+/// An archive from the release cache, if it has one that passes the signature check.
+fn fetch_cached(
+    cache: &ReleaseCache,
+    asset: &ReleaseAsset,
+    partial_path: &Path,
+    keyring: &Keyring,
+) -> Option<std::fs::File> {
+    let mut archive_file = cache.fetch(asset, partial_path)?;
+    if let Err(err) = verify_signature(&mut archive_file, asset.name.as_bytes(), keyring.keys()) {
+        tracing::warn!(
+            "Cached archive failed the signature check, downloading it instead: {:?}",
+            err
+        );
+        drop(archive_file);
+        std::fs::remove_file(partial_path).ok();
+        return None;
+    }
+    Some(archive_file)
+}
+
+/// Checks the downloaded size and digest against what the server and the release advertised.
+fn check_download(
+    asset: &ReleaseAsset,
+    content_length: Option<u64>,
+    written: u64,
+    digest: &str,
+) -> Result<(), String> {
+    if let Some(expected) = content_length.or(asset.size) {
+        if written != expected {
+            return Err(format!("received {written} of {expected} bytes"));
+        }
+    }
+
+    if let Some(expected) = asset
+        .digest
+        .as_deref()
+        .and_then(|d| d.strip_prefix("sha256:"))
     {
-        Some(entry.path().to_path_buf())
-    } else {
-        std::fs::copy(entry.path(), target_path).expect("Failed to copy file");
-        None
+        if !expected.eq_ignore_ascii_case(digest) {
+            return Err(format!(
+                "SHA-256 mismatch, expected {expected} but got {digest}"
+            ));
+        }
     }
+
+    Ok(())
+}
+
+fn extract_archive(
+    tmp_archive: &std::fs::File,
+    temp_dir: &tempfile::TempDir,
+) -> Result<(), UpdateError> {
+    tracing::debug!("Extracting archive to: {:?}", temp_dir.path());
+
+    extract::extract_archive(tmp_archive, temp_dir.path(), extract::DEFAULT_LIMITS)
+        .map_err(UpdateError::Extraction)
 }
 
-pub fn start_updater() {
-    if let Some(release) = get_update() {
+/// Offers an update found by an earlier check, then starts a new check in the background
+/// if the last one is older than the configured interval.
+pub fn start_updater(config: &mut Config) -> Option<UpdateCheck> {
+    if let Some(pinned) = &config.pinned_version {
         tracing::info!(
-            "Found new release: {}",
-            release.tag_name.trim_start_matches("v")
+            "Updates are pinned to {}, run with --unpin-version to resume them",
+            pinned
+        );
+        return None;
+    }
+
+    let channel = config.channel.unwrap_or_else(Channel::installed);
+    let mut cache = UpdateCache::load();
+    if let Some(pending) = cache.pending.take() {
+        // The update stays pending on disk, so a failed install is offered again
+        if let Err(err) = offer_update(pending, channel, config, &mut cache) {
+            handle_update_error(err);
+        }
+    }
+
+    let interval = config
+        .update_check_interval_minutes
+        .map(|minutes| Duration::from_secs(minutes * 60))
+        .unwrap_or(update_check::DEFAULT_CHECK_INTERVAL);
+    if !cache.is_due(interval, SystemTime::now()) {
+        tracing::info!("Checked for updates less than {:?} ago", interval);
+        return None;
+    }
+    // Recorded up front, as a check that fails or times out records nothing itself
+    cache.record_attempt(SystemTime::now());
+    cache.save();
+
+    let timeout = config
+        .update_check_timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(update_check::DEFAULT_CHECK_TIMEOUT);
+    let source = config.source.clone();
+    let skipped = config.skipped_version.clone();
+    Some(UpdateCheck::spawn(timeout, move || {
+        check_for_update(source.as_ref(), channel, skipped.as_deref())
+    }))
+}
+
+/// Fetches releases and caches the best update for the next launch.
+fn check_for_update(
+    source: Option<&SourceConfig>,
+    channel: Channel,
+    skipped: Option<&str>,
+) -> Result<Option<String>, UpdateError> {
+    let source = release_source::from_config(source);
+    tracing::info!("Update channel: {}, source: {}", channel, source.describe());
+
+    let releases = source.releases().map_err(UpdateError::Network)?;
+    let update = select_update(releases.clone(), VERSION, channel)
+        .filter(|release| Some(release.version_str()) != skipped);
+    let version = update.as_ref().map(|r| r.version_str().to_string());
+
+    let mut cache = UpdateCache::load();
+    cache.record_check(
+        SystemTime::now(),
+        update.map(|release| PendingUpdate {
+            notes: changelog::render(&releases, VERSION, &release, channel),
+            release,
+        }),
+    );
+    cache.save();
+    Ok(version)
+}
+
+/// Lets the user install, skip or postpone an update found earlier. Updates that no
+/// longer apply (installed since, skipped, off the current channel) are dropped.
+fn offer_update(
+    pending: PendingUpdate,
+    channel: Channel,
+    config: &mut Config,
+    cache: &mut UpdateCache,
+) -> Result<(), UpdateError> {
+    let release = &pending.release;
+    let still_applies = release.is_on_channel(channel)
+        && bump_is_greater(VERSION, release.version_str(), channel).unwrap_or(false)
+        && config.skipped_version.as_deref() != Some(release.version_str());
+    if !still_applies {
+        cache.save();
+        return Ok(());
+    }
+    tracing::info!("Found new release: {}", release.version_str());
+
+    match ask_update(release, &pending.notes, config) {
+        UpdateDecision::Install => {
+            let source = release_source::from_config(config.source.as_ref());
+            install_release(source.as_ref(), release, config, false)?
+        }
+        UpdateDecision::Skip => {
+            tracing::info!("Skipping {}", release.version_str());
+            config.skipped_version = Some(release.version_str().to_string());
+            config.save();
+            cache.save();
+        }
+        UpdateDecision::Postpone => {
+            tracing::info!("Postponing {} until the next launch", release.version_str());
+            cache.pending = Some(pending);
+            cache.save();
+        }
+    }
+    Ok(())
+}
+
+/// Shows the release notes and lets the user decide. Without a terminal to ask on, the
+/// configured unattended policy applies, installing by default.
+fn ask_update(release: &Release, notes: &str, config: &Config) -> UpdateDecision {
+    if !std::io::stdin().is_terminal() {
+        let decision = config.unattended_update.unwrap_or(UpdateDecision::Install);
+        tracing::info!("Not running interactively, update policy: {}", decision);
+        return decision;
+    }
+
+    println!(
+        "\nDEN {} is available. What's new:\n",
+        release.version_str()
+    );
+    println!("{}", notes);
+    println!("Use the arrow keys to navigate and Enter to select.\n");
+
+    let options = [
+        (UpdateDecision::Install, "Update now"),
+        (UpdateDecision::Skip, "Skip this version"),
+        (UpdateDecision::Postpone, "Remind me next launch"),
+    ];
+    let labels: Vec<_> = options.iter().map(|(_, label)| label).collect();
+    let &selected = Select::new(&labels, stdout()).start();
+    options
+        .iter()
+        .find(|(_, label)| label == selected)
+        .map(|(decision, _)| *decision)
+        .unwrap_or(UpdateDecision::Postpone)
+}
+
+/// Installs the release tagged `tag`, whether it is newer or older than the running
+/// version and whatever channel it is on. The archive is still signature checked.
+pub fn install_version(config: &Config, tag: &str) {
+    let source = release_source::from_config(config.source.as_ref());
+    let release = match source.releases() {
+        Ok(releases) => find_release(releases, tag),
+        Err(err) => {
+            tracing::error!("{}", err);
+            None
+        }
+    };
+    let Some(release) = release else {
+        tracing::error!("No release {} found on {}", tag, source.describe());
+        std::thread::sleep(std::time::Duration::from_secs(10));
+        std::process::exit(1);
+    };
+
+    if bump_is_greater(release.version_str(), VERSION, Channel::Beta).unwrap_or(false) {
+        tracing::warn!("Downgrading from {} to {}", VERSION, release.version_str());
+    }
+    if let Err(err) = install_release(source.as_ref(), &release, config, true) {
+        handle_update_error(err);
+    }
+}
+
+/// Finds a release by tag, with or without its `v` prefix. Drafts are never installed.
+fn find_release(releases: Vec<Release>, tag: &str) -> Option<Release> {
+    let version = tag.trim_start_matches('v');
+    releases
+        .into_iter()
+        .filter(|r| !r.draft)
+        .find(|r| r.tag_name == tag || r.version_str() == version)
+}
+
+fn install_release(
+    source: &dyn ReleaseSource,
+    release: &Release,
+    config: &Config,
+    allow_downgrade: bool,
+) -> Result<(), UpdateError> {
+    let Some(asset) = release
+        .assets
+        .iter()
+        .find(|asset| asset.name.ends_with(".zip"))
+    else {
+        tracing::warn!(
+            "Release {} has no archive to install",
+            release.version_str()
         );
+        return Ok(());
+    };
+    let retry = RetryPolicy::with_attempts(
+        config
+            .download_attempts
+            .unwrap_or(DEFAULT_DOWNLOAD_ATTEMPTS),
+    );
+    let mut keyring = Keyring::load();
+    update_keyring(source, release, retry, &mut keyring);
+    let expected = ReleaseExpectation {
+        version: Some(release.version_str().to_string()),
+        allow_downgrade,
+    };
+    let cache = config
+        .release_cache
+        .as_ref()
+        .map(|dir| ReleaseCache::new(exe_dir().join(dir)));
+    let exe = update_from_asset(
+        source,
+        release,
+        asset,
+        retry,
+        &keyring,
+        cache.as_ref(),
+        &expected,
+    )?;
+    relaunch(&exe)
+}
 
-        if let Some(asset) = release
-            .assets
-            .iter()
-            .find(|asset| asset.name.ends_with(".zip"))
-        {
-            update_from_asset(asset)
+/// Learns the key statements published with a release, so archives signed by a newly
+/// endorsed key verify and revoked keys stop being trusted.
+fn update_keyring(
+    source: &dyn ReleaseSource,
+    release: &Release,
+    retry: RetryPolicy,
+    keyring: &mut Keyring,
+) {
+    let Some(asset) = release.assets.iter().find(|a| a.name == KEYRING_ASSET) else {
+        return;
+    };
+    let partial_path = partial_download_path(release, asset);
+    if let Some(parent) = partial_path.parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+    std::fs::remove_file(&partial_path).ok();
+
+    let statements = source
+        .download(asset, &partial_path, retry)
+        .and_then(|(mut file, _)| {
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)
+                .map_err(|e| e.to_string())?;
+            keyring::parse_statements(&contents)
+        });
+    std::fs::remove_file(&partial_path).ok();
+
+    match statements {
+        Ok(statements) => {
+            if keyring.add_statements(statements) {
+                keyring.save();
+            }
         }
-        tracing::info!("Update complete, please restart the launcher");
-        std::thread::sleep(std::time::Duration::from_secs(5));
-        std::process::exit(0);
+        Err(err) => tracing::warn!("Failed to fetch release key statements: {}", err),
     }
 }
 
+/// Restores the install that was replaced by the last update.
+pub fn rollback() {
+    for pid in get_pids_by_name(ELDENRING_EXE) {
+        kill_process(pid);
+    }
+
+    match Install::current().rollback(VERSION) {
+        Ok(version) => tracing::info!("Rolled back to {}, please restart the launcher", version),
+        Err(err) => tracing::error!("Failed to roll back: {}", err),
+    }
+    std::thread::sleep(std::time::Duration::from_secs(5));
+    std::process::exit(0);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_archive;
+
+    fn release(tag: &str) -> Release {
+        Release {
+            tag_name: tag.to_string(),
+            prerelease: false,
+            draft: false,
+            body: None,
+            assets: Vec::new(),
+        }
+    }
 
     #[test]
     fn test_bump_is_greater() {
-        assert_eq!(bump_is_greater("1.0.0", "1.0.1"), Some(true));
-        assert_eq!(bump_is_greater("1.0.1", "1.0.0"), Some(false));
-        assert_eq!(bump_is_greater("1.0.0", "1.0.0"), Some(false));
-        assert_eq!(bump_is_greater("1.0.0", "invalid"), None);
-        assert_eq!(bump_is_greater("invalid", "1.0.0"), None);
+        let beta = Channel::Beta;
+        assert_eq!(bump_is_greater("1.0.0", "1.0.1", beta), Some(true));
+        assert_eq!(bump_is_greater("1.0.1", "1.0.0", beta), Some(false));
+        assert_eq!(bump_is_greater("1.0.0", "1.0.0", beta), Some(false));
+        assert_eq!(bump_is_greater("1.0.0", "invalid", beta), None);
+        assert_eq!(bump_is_greater("invalid", "1.0.0", beta), None);
+        assert_eq!(
+            bump_is_greater("2.0.0-beta.10", "2.0.0-beta.9", beta),
+            Some(false)
+        );
         assert_eq!(
-            bump_is_greater("2.0.0-beta.10", "2.0.0-beta.9"),
+            bump_is_greater("2.0.0-beta9", "2.0.0-beta.10", beta),
             Some(false)
         );
-        assert_eq!(bump_is_greater("2.0.0-beta9", "2.0.0-beta.10"), Some(false));
         assert_eq!(
-            bump_is_greater("2.0.0-rc.1", "2.0.0-rc.1+patch.1"),
+            bump_is_greater("2.0.0-rc.1", "2.0.0-rc.1+patch.1", beta),
             Some(true)
         );
     }
+
+    #[test]
+    fn test_bump_is_greater_channels() {
+        assert_eq!(
+            bump_is_greater("1.0.0", "1.1.0-rc.1", Channel::Stable),
+            Some(false)
+        );
+        assert_eq!(
+            bump_is_greater("1.0.0", "1.1.0-beta.1", Channel::Stable),
+            Some(false)
+        );
+        assert_eq!(
+            bump_is_greater("1.0.0", "1.1.0", Channel::Stable),
+            Some(true)
+        );
+        assert_eq!(
+            bump_is_greater("1.0.0", "1.1.0-rc.1", Channel::Rc),
+            Some(true)
+        );
+        assert_eq!(
+            bump_is_greater("1.0.0", "1.1.0-beta.1", Channel::Rc),
+            Some(false)
+        );
+        assert_eq!(
+            bump_is_greater("1.0.0", "1.1.0-beta.1", Channel::Beta),
+            Some(true)
+        );
+        assert_eq!(
+            bump_is_greater("1.1.0-rc.1", "1.1.0", Channel::Stable),
+            Some(true)
+        );
+        assert_eq!(
+            bump_is_greater("2.0.0-rc.5", "2.0.0-rc.4", Channel::Rc),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_release_prerelease_flag() {
+        let release = Release {
+            prerelease: true,
+            ..release("v1.1.0")
+        };
+        assert!(!release.is_on_channel(Channel::Stable));
+        assert!(release.is_on_channel(Channel::Rc));
+
+        let draft = Release {
+            draft: true,
+            prerelease: false,
+            ..release
+        };
+        assert!(!draft.is_on_channel(Channel::Beta));
+    }
+
+    #[test]
+    fn test_channel_parse() {
+        assert_eq!("stable".parse(), Ok(Channel::Stable));
+        assert_eq!("RC".parse(), Ok(Channel::Rc));
+        assert_eq!("beta".parse(), Ok(Channel::Beta));
+        assert!("nightly".parse::<Channel>().is_err());
+    }
+
+    #[test]
+    fn test_select_update_picks_highest() {
+        let releases = vec![
+            release("v2.0.1"),
+            release("v2.1.0"),
+            release("v1.9.0"),
+            release("v2.0.5"),
+        ];
+        let update = select_update(releases, "2.0.0", Channel::Stable).unwrap();
+        assert_eq!(update.tag_name, "v2.1.0");
+    }
+
+    #[test]
+    fn test_select_update_build_metadata() {
+        let releases = vec![
+            release("v2.0.0-rc.5+patch.10"),
+            release("v2.0.0-rc.5+patch.3"),
+            release("v2.0.0-rc.5+patch.2"),
+        ];
+        let update = select_update(releases, "2.0.0-rc.5+patch.2", Channel::Rc).unwrap();
+        assert_eq!(update.tag_name, "v2.0.0-rc.5+patch.10");
+
+        let releases = vec![release("v2.0.0-rc.5+patch.2")];
+        assert!(select_update(releases, "2.0.0-rc.5+patch.2", Channel::Rc).is_none());
+    }
+
+    #[test]
+    fn test_check_download() {
+        let digest = "ab".repeat(32);
+        let mut asset = ReleaseAsset {
+            url: String::new(),
+            name: "den.zip".to_string(),
+            size: Some(1024),
+            digest: Some(format!("sha256:{digest}")),
+        };
+        assert!(check_download(&asset, Some(1024), 1024, &digest).is_ok());
+        assert!(check_download(&asset, Some(1025), 1024, &digest).is_err());
+        assert!(check_download(&asset, None, 1024, &digest).is_ok());
+        assert!(check_download(&asset, None, 1000, &digest).is_err());
+
+        asset.digest = Some(format!("sha256:{}", "0".repeat(64)));
+        assert!(check_download(&asset, Some(1024), 1024, &digest).is_err());
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let key = test_archive::test_key(1);
+        let zip = test_archive::zip_bytes(&[("DENData/denmaps.dll", b"dll")]);
+        let dir = tempfile::TempDir::new().unwrap();
+        let trusted = [test_archive::public_key(&key)];
+
+        let signed = dir.path().join("DEN.zip");
+        std::fs::write(&signed, test_archive::sign_zip(&zip, &key, "DEN.zip")).unwrap();
+        let mut file = std::fs::File::open(&signed).unwrap();
+        assert!(verify_signature(&mut file, b"DEN.zip", &trusted).is_ok());
+
+        // Renamed after signing
+        let mut file = std::fs::File::open(&signed).unwrap();
+        assert!(verify_signature(&mut file, b"DEN-renamed.zip", &trusted).is_err());
+
+        // Signed by someone else
+        let other = test_archive::sign_zip(&zip, &test_archive::test_key(2), "DEN.zip");
+        std::fs::write(&signed, other).unwrap();
+        let mut file = std::fs::File::open(&signed).unwrap();
+        assert!(verify_signature(&mut file, b"DEN.zip", &trusted).is_err());
+
+        // Every key revoked
+        std::fs::write(&signed, test_archive::sign_zip(&zip, &key, "DEN.zip")).unwrap();
+        let mut file = std::fs::File::open(&signed).unwrap();
+        assert!(verify_signature(&mut file, b"DEN.zip", &[]).is_err());
+
+        // Not signed at all
+        std::fs::write(&signed, &zip).unwrap();
+        let mut file = std::fs::File::open(&signed).unwrap();
+        assert!(verify_signature(&mut file, b"DEN.zip", &trusted).is_err());
+    }
+
+    #[test]
+    fn test_update_errors() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let not_a_zip = dir.path().join("DEN.zip");
+        std::fs::write(&not_a_zip, b"not a zip").unwrap();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let err = extract_archive(&std::fs::File::open(&not_a_zip).unwrap(), &tmp_dir);
+        assert!(matches!(err, Err(UpdateError::Extraction(_))));
+
+        // Checked before anything is installed
+        let expected = ReleaseExpectation {
+            version: Some("99.0.0".to_string()),
+            allow_downgrade: false,
+        };
+        let err = install_extracted(&tmp_dir, &expected).unwrap_err();
+        assert!(matches!(err, UpdateError::Signature(_)));
+        assert!(err.is_recoverable());
+        assert!(!UpdateError::SelfReplace("swap failed".to_string()).is_recoverable());
+    }
+
+    #[test]
+    fn test_fetch_cached_checks_signature() {
+        let key = test_archive::test_key(1);
+        let keyring = Keyring::new(vec![test_archive::public_key(&key)], Vec::new(), Vec::new());
+        let zip = test_archive::zip_bytes(&[("DENData/denmaps.dll", b"dll")]);
+        let share = tempfile::TempDir::new().unwrap();
+        let local = tempfile::TempDir::new().unwrap();
+        let cache = ReleaseCache::new(share.path().to_path_buf());
+        let partial_path = local.path().join("DEN.zip.part");
+
+        for (signer, trusted) in [(test_archive::test_key(2), false), (key, true)] {
+            let archive = local.path().join("DEN.zip");
+            std::fs::write(&archive, test_archive::sign_zip(&zip, &signer, "DEN.zip")).unwrap();
+            let digest =
+                download::file_sha256(&mut std::fs::File::open(&archive).unwrap()).unwrap();
+            cache.store("DEN.zip", &digest, &archive);
+            let asset = ReleaseAsset {
+                url: "http://localhost/DEN.zip".to_string(),
+                name: "DEN.zip".to_string(),
+                size: None,
+                digest: Some(format!("sha256:{digest}")),
+            };
+
+            let cached = fetch_cached(&cache, &asset, &partial_path, &keyring);
+            assert_eq!(cached.is_some(), trusted);
+            assert_eq!(partial_path.exists(), trusted);
+        }
+    }
+
+    #[test]
+    fn test_partial_download_path() {
+        let asset = ReleaseAsset {
+            url: "http://localhost/DEN.zip".to_string(),
+            name: "DEN.zip".to_string(),
+            size: None,
+            digest: None,
+        };
+        let a = partial_download_path(&release("v2.0.0"), &asset);
+        let b = partial_download_path(&release("v2.1.0"), &asset);
+        assert_ne!(a, b);
+        assert!(a.ends_with("v2.0.0/DEN.zip.part"));
+        assert!(partial_download_path(&release("../v2.1.0"), &asset).starts_with(downloads_dir()));
+
+        let downloads = tempfile::TempDir::new().unwrap();
+        let current = downloads.path().join("v2.1.0");
+        for dir in ["v2.0.0", "v2.1.0"] {
+            std::fs::create_dir_all(downloads.path().join(dir)).unwrap();
+            std::fs::write(downloads.path().join(dir).join("DEN.zip.part"), b"partial").unwrap();
+        }
+        std::fs::write(downloads.path().join("DEN.zip.part"), b"partial").unwrap();
+        remove_stale_downloads(downloads.path(), &current);
+        let left: Vec<_> = std::fs::read_dir(downloads.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(left, vec![current.clone()]);
+        assert!(current.join("DEN.zip.part").is_file());
+    }
+
+    #[test]
+    fn test_find_release() {
+        let mut draft = release("v1.3.0");
+        draft.draft = true;
+        let releases = vec![release("v1.1.0"), release("1.2.0-beta.1"), draft];
+
+        assert_eq!(
+            find_release(releases.clone(), "v1.1.0").unwrap().tag_name,
+            "v1.1.0"
+        );
+        assert_eq!(
+            find_release(releases.clone(), "1.1.0").unwrap().tag_name,
+            "v1.1.0"
+        );
+        assert_eq!(
+            find_release(releases.clone(), "v1.2.0-beta.1")
+                .unwrap()
+                .tag_name,
+            "1.2.0-beta.1"
+        );
+        assert!(find_release(releases.clone(), "v1.3.0").is_none());
+        assert!(find_release(releases, "v9.9.9").is_none());
+    }
+
+    /// An extracted release of `version` with metadata matching its files.
+    fn extracted_release(version: &str) -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("DENData")).unwrap();
+        std::fs::write(dir.path().join("DENData/denmaps.dll"), "dll").unwrap();
+        let metadata = ReleaseMetadata {
+            version: version.to_string(),
+            build_date: "2025-02-01".to_string(),
+            files: crate::manifest::Manifest::build(dir.path()).unwrap().files,
+        };
+        std::fs::write(
+            dir.path().join(RELEASE_METADATA_FILE),
+            serde_json::to_string(&metadata).unwrap(),
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_release_expectation() {
+        let update = |version: &str| ReleaseExpectation {
+            version: Some(version.to_string()),
+            allow_downgrade: false,
+        };
+        let release = extracted_release("2.1.0");
+        assert!(update("v2.1.0").check(release.path(), "2.0.0").is_ok());
+
+        // An older release replayed under a newer tag
+        assert!(update("v2.2.0").check(release.path(), "2.0.0").is_err());
+        // Not newer than what is installed
+        assert!(update("v2.1.0").check(release.path(), "2.1.0").is_err());
+        assert!(update("v2.1.0").check(release.path(), "2.2.0").is_err());
+        // A broken installed version is an error, not a panic
+        assert!(update("v2.1.0").check(release.path(), "dev").is_err());
+
+        let downgrade = ReleaseExpectation {
+            version: Some("v2.1.0".to_string()),
+            allow_downgrade: true,
+        };
+        assert!(downgrade.check(release.path(), "2.2.0").is_ok());
+    }
+
+    #[test]
+    fn test_release_expectation_checks_files() {
+        let expected = ReleaseExpectation {
+            version: None,
+            allow_downgrade: false,
+        };
+        let release = extracted_release("2.1.0");
+        std::fs::write(release.path().join("DENData/denmaps.dll"), "tampered").unwrap();
+        assert!(expected.check(release.path(), "2.0.0").is_err());
+
+        let release = extracted_release("2.1.0");
+        std::fs::write(release.path().join("DENData/extra.dll"), "extra").unwrap();
+        assert!(expected.check(release.path(), "2.0.0").is_err());
+
+        let release = extracted_release("2.1.0");
+        std::fs::remove_file(release.path().join(RELEASE_METADATA_FILE)).unwrap();
+        assert!(expected.check(release.path(), "2.0.0").is_err());
+    }
+
+    #[test]
+    fn test_relaunch_args() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            relaunch_args(args(&["DEN-Launcher.exe", "--channel", "beta"]).into_iter()),
+            args(&["--channel", "beta", RELAUNCHED_FLAG])
+        );
+        // Relaunching twice doesn't pile up markers
+        assert_eq!(
+            relaunch_args(args(&["DEN-Launcher.exe", RELAUNCHED_FLAG]).into_iter()),
+            args(&[RELAUNCHED_FLAG])
+        );
+    }
 }